@@ -0,0 +1,103 @@
+//! Output throughput of `SystemStdio` compared to writing every byte straight to stdout.
+//!
+//! Run with `cargo bench --bench stdio`. Each measurement runs this executable again as a child
+//! process and reads its output through a pipe, so that the output does not reach the terminal.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use rings::{
+    build::ProgramAssembler,
+    io::{RingsIo, SystemStdio},
+    vm::RingsVM,
+};
+
+const CHILD_ENV: &str = "RINGS_BENCH_STDIO_CHILD";
+const RUNS: usize = 5;
+
+/// Outputs 16 * 256 * 256 bytes, one `out` per 3 instructions
+const PROGRAM: &str = "
+mkr 1               # Ring 0 – Output cell
+mkr 1               # Ring 1 – Inner counter
+mkr 1               # Ring 2 – Middle counter
+mkr 1               # Ring 3 – Outer counter
+mkr 1 put 4 1       # Ring 4 – Constant 1
+mkr 1               # Ring 5 – Constant 0
+mkr 1 put 6 16      # Ring 6 – Outer limit
+
+:loop
+    out 0
+    add 1 4 1
+    jgt 1 5 :loop
+    add 2 4 2
+    jgt 2 5 :loop
+    add 3 4 3
+    jlt 3 6 :loop
+";
+const OUTPUT_BYTES: usize = 16 * 256 * 256;
+
+/// Unbuffered stdout, one write per byte
+struct UnbufferedStdio;
+
+impl RingsIo for UnbufferedStdio {
+    fn inp(&mut self, _vm: &RingsVM) -> Option<u8> {
+        None
+    }
+
+    fn out(&mut self, value: u8, _vm: &RingsVM) {
+        let _ = std::io::stdout().write_all(&[value]);
+    }
+
+    fn err(&mut self, value: u8, _vm: &RingsVM) {
+        let _ = std::io::stderr().write_all(&[value]);
+    }
+}
+
+fn child(mode: &str) {
+    let program = ProgramAssembler::assemble(PROGRAM.as_bytes(), false).unwrap();
+    match mode {
+        "unbuffered" => RingsVM::execute(&program, &mut UnbufferedStdio).unwrap(),
+        _ => RingsVM::execute(&program, &mut SystemStdio::new()).unwrap(),
+    };
+}
+
+fn measure(mode: &str) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            let output = Command::new(std::env::current_exe().unwrap())
+                .env(CHILD_ENV, mode)
+                .stderr(Stdio::inherit())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{} run failed", mode);
+            assert_eq!(output.stdout.len(), OUTPUT_BYTES);
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+fn main() {
+    if let Ok(mode) = std::env::var(CHILD_ENV) {
+        return child(&mode);
+    }
+
+    println!("{} bytes of output, best of {} runs", OUTPUT_BYTES, RUNS);
+
+    let mut results = Vec::new();
+    for mode in ["unbuffered", "buffered"] {
+        let time = measure(mode);
+        let throughput = OUTPUT_BYTES as f64 / time.as_secs_f64() / (1024.0 * 1024.0);
+        println!("{:<12}{:>10.1?}{:>10.2} MiB/s", mode, time, throughput);
+        results.push(time);
+    }
+
+    println!(
+        "speedup     {:>10.1}x",
+        results[0].as_secs_f64() / results[1].as_secs_f64()
+    );
+}
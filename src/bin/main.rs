@@ -1,38 +1,445 @@
-#![feature(try_trait_v2)]
-use std::{fs::File, path::PathBuf};
+use std::{
+    fs::File,
+    io::{BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use rings::{
-    build::ProgramAssembler,
-    error::{MaybeLocalizedRingsResult, RingsError},
+    build::{Program, ProgramAssembler},
+    debugger::Debugger,
+    error::{Diagnostics, DiagnosticsResult, RingsError},
+    format::format_source,
+    golden::{GoldenOutcome, GoldenTest},
     io::SystemStdio,
-    vm::RingsVM,
+    render::DiagnosticRenderer,
+    trace::{TraceFormat, Tracer},
+    vm::{DivisionByZeroPolicy, EofPolicy, Limits, RingsVM, EXIT_RUNTIME_ERROR},
+    Sources,
 };
 
+/// Exit status for errors found while reading or assembling the program
+const EXIT_ASSEMBLY_ERROR: u8 = 65;
+/// Exit status for I/O errors, e.g. when the program file cannot be opened
+const EXIT_IO_ERROR: u8 = 74;
+/// Exit status of `rings test` when at least one test fails, of `rings fmt --check`
+/// when the file is not formatted and of `rings check` when there are warnings
+const EXIT_TEST_FAILURE: u8 = 1;
+
+const EXIT_STATUS_HELP: &str = "\
+EXIT STATUS:
+    The exit code of HLT, or 0 when the program runs past its last instruction.
+    1     Failed tests (rings test), unformatted file (rings fmt --check), warnings (rings check)
+    65    Assembly error (invalid UTF-8, syntax error, unknown label, invalid bytecode, ...)
+    70    Runtime error (invalid ring, division by zero, exceeded limit, ...)
+    74    I/O error
+    2     Invalid command line arguments
+    Note that HLT may exit with any of these codes as well.";
+
 #[derive(Parser, Debug)]
 #[clap(author = "Marek Miklenda")]
 #[clap(version)]
 #[clap(about="Rings interpreter", long_about = None)]
+#[clap(after_help = EXIT_STATUS_HELP)]
 struct Args {
-    /// File to run
+    #[clap(subcommand)]
+    command: Command,
+
+    /// When to use colors in error messages
+    #[clap(long, arg_enum, global = true, default_value = "auto")]
+    color: ColorChoice,
+
+    /// Directory to search for included files that are not found next to the including file.
+    /// Can be given multiple times
+    #[clap(short = 'I', long, global = true, value_name = "DIR")]
+    include_path: Vec<PathBuf>,
+}
+
+#[derive(clap::ArgEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            Self::Auto => std::env::var_os("NO_COLOR").is_none() && std::io::stderr().is_terminal(),
+            Self::Always => true,
+            Self::Never => false,
+        }
+    }
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a program
+    Run(RunArgs),
+    /// Assemble a program into bytecode
+    Build(BuildArgs),
+    /// Debug a program interactively
+    Debug(DebugArgs),
+    /// Print a program as Rings source, e.g. to read bytecode
+    Disasm(DisasmArgs),
+    /// Format a source file in place
+    Fmt(FmtArgs),
+    /// Check a program for likely mistakes
+    Check(CheckArgs),
+    /// Run golden-file tests: every foo.rn in a directory is run with foo.in as input
+    /// and checked against foo.out, foo.err and foo.code, where present
+    Test(TestArgs),
+}
+
+impl Command {
+    fn file(&self) -> &Path {
+        match self {
+            Self::Run(args) => &args.file,
+            Self::Build(args) => &args.file,
+            Self::Debug(args) => &args.file,
+            Self::Disasm(args) => &args.file,
+            Self::Fmt(args) => &args.file,
+            Self::Check(args) => &args.file,
+            Self::Test(args) => &args.dir,
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+#[clap(after_help = EXIT_STATUS_HELP)]
+struct RunArgs {
+    /// File to run, either source or bytecode
     file: PathBuf,
 
     /// Disable debugging. No trace will be provided on error.
+    #[clap(short, long, action)]
+    no_debug: bool,
+
+    /// Emit one trace record per executed instruction
     #[clap(long, action)]
+    trace: bool,
+
+    /// Trace format: text or json (JSON Lines)
+    #[clap(long, default_value = "text")]
+    trace_format: TraceFormat,
+
+    /// Write the trace to a file instead of stderr
+    #[clap(long)]
+    trace_output: Option<PathBuf>,
+
+    /// Abort after executing this many instructions
+    #[clap(long)]
+    max_steps: Option<u64>,
+
+    /// Abort when the program creates more than this many rings
+    #[clap(long)]
+    max_rings: Option<usize>,
+
+    /// Abort when the total length of all rings exceeds this many bytes
+    #[clap(long)]
+    max_memory: Option<usize>,
+
+    /// Behavior of DIV with a zero divisor: "error", or a value (e.g. 0 or 0xFF) to store as the result
+    #[clap(long, default_value = "error")]
+    div_by_zero: DivisionByZeroPolicy,
+
+    /// Behavior of INP at the end of input: "error", "unchanged", or a value to store in the ring
+    #[clap(long, default_value = "0xFF")]
+    eof: EofPolicy,
+}
+
+#[derive(clap::Args, Debug)]
+struct BuildArgs {
+    /// File to assemble
+    file: PathBuf,
+
+    /// Output file [default: FILE with the extension .rnb]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+
+    /// Leave out the debug section with source locations and label names
+    #[clap(short, long, action)]
     no_debug: bool,
 }
 
-fn main_wrapped() -> MaybeLocalizedRingsResult<u8> {
-    let args = Args::parse();
+#[derive(clap::Args, Debug)]
+#[clap(after_help = EXIT_STATUS_HELP)]
+struct DebugArgs {
+    /// File to debug
+    file: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct TestArgs {
+    /// Directory to search for tests
+    dir: PathBuf,
+
+    /// Fail a test after executing this many instructions
+    #[clap(long, default_value = "10000000")]
+    max_steps: u64,
+}
+
+#[derive(clap::Args, Debug)]
+struct DisasmArgs {
+    /// File to disassemble, either source or bytecode
+    file: PathBuf,
+
+    /// Output file [default: stdout]
+    #[clap(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(clap::Args, Debug)]
+struct CheckArgs {
+    /// File to check
+    file: PathBuf,
+}
+
+#[derive(clap::Args, Debug)]
+struct FmtArgs {
+    /// File to format
+    file: PathBuf,
 
-    let program_file = File::open(args.file).map_err(RingsError::from)?;
-    let program = ProgramAssembler::assemble(program_file, !args.no_debug)?.unwrap();
+    /// Do not write the file, fail if it is not formatted
+    #[clap(long, action)]
+    check: bool,
+}
 
-    RingsVM::execute(&program, &mut SystemStdio)
+/// The program file given on the command line
+struct Input {
+    path: PathBuf,
+    source: Vec<u8>,
+    include_path: Vec<PathBuf>,
+    /// The source files read while assembling, for rendering diagnostics
+    sources: Sources,
 }
 
-fn main() {
-    if let Some(e) = main_wrapped().into_err() {
-        eprintln!("{}", e);
+impl Input {
+    fn assemble(&mut self, preserve_location: bool) -> DiagnosticsResult<Program> {
+        ProgramAssembler::assemble_file(
+            &self.path,
+            &self.source,
+            &self.include_path,
+            preserve_location,
+            &mut self.sources,
+        )
+    }
+
+    /// Reads a program from source or bytecode
+    fn load(&mut self, preserve_location: bool) -> DiagnosticsResult<Program> {
+        if Program::is_bytecode(&self.source) {
+            Ok(Program::from_bytes(&self.source, &mut self.sources).map_err(RingsError::from)?)
+        } else {
+            self.assemble(preserve_location)
+        }
+    }
+}
+
+fn run(args: RunArgs, input: &mut Input) -> DiagnosticsResult<u8> {
+    let program = input.load(!args.no_debug)?;
+    let mut vm = RingsVM::new(program);
+    vm.limits = Limits {
+        max_steps: args.max_steps,
+        max_rings: args.max_rings,
+        max_memory: args.max_memory,
+    };
+    vm.division_by_zero = args.div_by_zero;
+    vm.end_of_input = args.eof;
+
+    let result = if args.trace {
+        let trace_output: Box<dyn Write> = match args.trace_output {
+            Some(path) => Box::new(File::create(path).map_err(RingsError::from)?),
+            None => Box::new(std::io::stderr()),
+        };
+
+        Tracer::new(BufWriter::new(trace_output), args.trace_format)
+            .run(&mut vm, &mut SystemStdio::new())
+    } else {
+        vm.run(&mut SystemStdio::new())
+    };
+
+    result.map_err(|e| vm.diagnostics(e))
+}
+
+fn build(args: BuildArgs, input: &mut Input) -> DiagnosticsResult<u8> {
+    let program = input.assemble(!args.no_debug)?;
+    let output = args
+        .output
+        .unwrap_or_else(|| args.file.with_extension("rnb"));
+
+    let bytes = program.to_bytes(&input.sources).map_err(RingsError::from)?;
+    std::fs::write(output, bytes).map_err(RingsError::from)?;
+    Ok(0)
+}
+
+fn debug(_args: DebugArgs, input: &mut Input) -> DiagnosticsResult<u8> {
+    let program = input.assemble(true)?;
+
+    // Commands are read a line at a time from the buffer of stdin that INP reads from as well,
+    // so piped input is split between the debugger and the program in order
+    let commands = std::iter::from_fn(|| {
+        let mut line = String::new();
+        match std::io::stdin().read_line(&mut line) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(line)),
+            Err(e) => Some(Err(e)),
+        }
+    });
+
+    let mut debugger = Debugger::with_sources(program, &input.sources);
+    let exit_code = debugger
+        .repl(&mut SystemStdio::new(), commands, std::io::stdout().lock())
+        .map_err(RingsError::from)?;
+
+    Ok(exit_code.unwrap_or_default())
+}
+
+fn disasm(args: DisasmArgs, input: &mut Input) -> DiagnosticsResult<u8> {
+    let program = input.load(true)?;
+    match args.output {
+        Some(path) => std::fs::write(path, program.disassemble()),
+        None => std::io::stdout().write_all(program.disassemble().as_bytes()),
+    }
+    .map_err(RingsError::from)?;
+
+    Ok(0)
+}
+
+fn fmt(args: FmtArgs, source: &[u8]) -> DiagnosticsResult<u8> {
+    let formatted = format_source(source)?;
+    if formatted.as_bytes() == source {
+        return Ok(0);
+    }
+
+    if args.check {
+        eprintln!("{} is not formatted", args.file.display());
+        return Ok(EXIT_TEST_FAILURE);
+    }
+
+    std::fs::write(&args.file, formatted).map_err(RingsError::from)?;
+    Ok(0)
+}
+
+fn check(_args: CheckArgs, input: &mut Input, color: bool) -> DiagnosticsResult<u8> {
+    let warnings = ProgramAssembler::check_file(
+        &input.path,
+        &input.source,
+        &input.include_path,
+        &mut input.sources,
+    )?;
+    if warnings.is_empty() {
+        return Ok(0);
+    }
+
+    let renderer = DiagnosticRenderer::with_sources(&input.sources, color);
+    let _ = renderer.render_warnings(&warnings, &mut std::io::stderr().lock());
+    Ok(EXIT_TEST_FAILURE)
+}
+
+fn test(args: TestArgs, color: bool) -> DiagnosticsResult<u8> {
+    let tests = GoldenTest::discover(&args.dir).map_err(RingsError::from)?;
+    let limits = Limits {
+        max_steps: Some(args.max_steps),
+        ..Limits::default()
+    };
+
+    let mut out = std::io::stdout().lock();
+    let mut failed = 0;
+    for test in &tests {
+        let report = test.run(limits);
+        let file_name = report.path.to_string_lossy();
+
+        match &report.outcome {
+            GoldenOutcome::Passed => {
+                let _ = writeln!(out, "PASS {}", file_name);
+                continue;
+            }
+            GoldenOutcome::Failed(mismatches) => {
+                let _ = writeln!(out, "FAIL {}", file_name);
+                for mismatch in mismatches {
+                    let _ = writeln!(out, "  {}", mismatch);
+                }
+            }
+            GoldenOutcome::Error(diagnostics) => {
+                let _ = writeln!(out, "FAIL {}", file_name);
+                let renderer = DiagnosticRenderer::with_sources(&report.sources, color);
+                let _ = renderer.render(diagnostics, &mut out);
+            }
+        }
+
+        failed += 1;
+    }
+
+    let _ = writeln!(
+        out,
+        "\n{} passed, {} failed, {} total",
+        tests.len() - failed,
+        failed,
+        tests.len()
+    );
+
+    Ok(if failed == 0 { 0 } else { EXIT_TEST_FAILURE })
+}
+
+fn error_status(diagnostics: &Diagnostics) -> u8 {
+    let Some(diagnostic) = diagnostics.iter().next() else {
+        return 0;
+    };
+
+    match diagnostic.error.as_ref() {
+        RingsError::IoError(..) => EXIT_IO_ERROR,
+        RingsError::CharacterRead(..)
+        | RingsError::Tokenizer(..)
+        | RingsError::StatementParser(..)
+        | RingsError::Assembler(..)
+        | RingsError::Bytecode(..) => EXIT_ASSEMBLY_ERROR,
+        RingsError::Runtime(..) => EXIT_RUNTIME_ERROR,
+    }
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    let path = args.command.file().to_owned();
+
+    let mut input = Input {
+        path,
+        source: Vec::new(),
+        include_path: args.include_path,
+        sources: Sources::new(),
+    };
+    let result = match args.command {
+        Command::Test(command) => test(command, args.color.enabled()),
+        command => File::open(&input.path)
+            .and_then(|mut file| file.read_to_end(&mut input.source))
+            .map_err(|e| Diagnostics::from(RingsError::from(e)))
+            .and_then(|_| match command {
+                Command::Run(command) => run(command, &mut input),
+                Command::Build(command) => build(command, &mut input),
+                Command::Debug(command) => debug(command, &mut input),
+                Command::Disasm(command) => disasm(command, &mut input),
+                Command::Fmt(command) => fmt(command, &input.source),
+                Command::Check(command) => check(command, &mut input, args.color.enabled()),
+                Command::Test(..) => unreachable!(),
+            }),
+    };
+
+    match result {
+        Ok(exit_code) => ExitCode::from(exit_code),
+        Err(diagnostics) => {
+            // Bytecode without a debug section has no source files to show
+            let source = match Program::is_bytecode(&input.source) {
+                true => Default::default(),
+                false => String::from_utf8_lossy(&input.source),
+            };
+            let file_name = input.path.to_string_lossy();
+            let color = args.color.enabled();
+            let renderer = match input.sources.iter().next() {
+                Some(..) => DiagnosticRenderer::with_sources(&input.sources, color),
+                None => DiagnosticRenderer::new(&file_name, &source, color),
+            };
+            let _ = renderer.render(&diagnostics, &mut std::io::stderr().lock());
+            ExitCode::from(error_status(&diagnostics))
+        }
     }
 }
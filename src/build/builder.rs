@@ -0,0 +1,152 @@
+use super::{AssemblerError, Program};
+use crate::{
+    error::{Diagnostics, DiagnosticsResult, RingsError},
+    instruction::{Instruction, Label},
+    vm::RingId,
+};
+
+/// Handle to a label created by [`ProgramBuilder::label`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct LabelId(usize);
+
+struct LabelSlot {
+    name: String,
+    address: Option<Label>,
+    bound_twice: bool,
+}
+
+/// Builds a [`Program`] from [`Instruction`]s instead of source text. Jumps may refer to labels
+/// that are bound later; [`ProgramBuilder::finish`] resolves them and validates the program.
+///
+/// ```
+/// use rings::{build::builder::ProgramBuilder, instruction::Instruction, io::BufferIo, vm::RingsVM};
+///
+/// let mut builder = ProgramBuilder::new();
+/// let end = builder.label("end");
+///
+/// builder.push(Instruction::MKR(1));
+/// builder.push(Instruction::INP(0));
+/// builder.jmp(end);
+/// builder.push(Instruction::OUT(0));
+/// builder.bind(end);
+/// builder.push(Instruction::HLT(3));
+///
+/// let program = builder.finish().unwrap();
+/// let mut io = BufferIo::new(b"A");
+/// assert_eq!(RingsVM::execute(&program, &mut io).unwrap(), 3);
+/// assert_eq!(io.output(), b"");
+/// ```
+#[derive(Default)]
+pub struct ProgramBuilder {
+    labels: Vec<LabelSlot>,
+    instructions: Vec<Instruction>,
+    /// Jumps to resolve: instruction index and target
+    jumps: Vec<(usize, LabelId)>,
+}
+
+impl ProgramBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Address of the next instruction
+    pub fn address(&self) -> Label {
+        self.instructions.len()
+    }
+
+    /// Creates an unbound label. The name is only used in error messages.
+    pub fn label<S>(&mut self, name: S) -> LabelId
+    where
+        S: Into<String>,
+    {
+        self.labels.push(LabelSlot {
+            name: name.into(),
+            address: None,
+            bound_twice: false,
+        });
+        LabelId(self.labels.len() - 1)
+    }
+
+    /// Binds `label` to the address of the next instruction
+    pub fn bind(&mut self, label: LabelId) {
+        let address = self.address();
+        let slot = &mut self.labels[label.0];
+        slot.bound_twice |= slot.address.is_some();
+        slot.address = Some(address);
+    }
+
+    /// Appends an instruction. Jump targets are taken as absolute addresses.
+    pub fn push(&mut self, instruction: Instruction) {
+        self.instructions.push(instruction);
+    }
+
+    fn push_jump(&mut self, instruction: Instruction, target: LabelId) {
+        self.jumps.push((self.instructions.len(), target));
+        self.push(instruction);
+    }
+
+    pub fn jmp(&mut self, target: LabelId) {
+        self.push_jump(Instruction::JMP(0), target);
+    }
+
+    pub fn jeq(&mut self, a: RingId, b: RingId, target: LabelId) {
+        self.push_jump(Instruction::JEQ(a, b, 0), target);
+    }
+
+    pub fn jgt(&mut self, a: RingId, b: RingId, target: LabelId) {
+        self.push_jump(Instruction::JGT(a, b, 0), target);
+    }
+
+    pub fn jlt(&mut self, a: RingId, b: RingId, target: LabelId) {
+        self.push_jump(Instruction::JLT(a, b, 0), target);
+    }
+
+    /// Resolves the jumps and validates the instructions, reporting every unbound or
+    /// twice bound label and every invalid instruction
+    pub fn finish(mut self) -> DiagnosticsResult<Program> {
+        let mut diagnostics = Diagnostics::new();
+
+        for slot in self.labels.iter().filter(|slot| slot.bound_twice) {
+            diagnostics.push(RingsError::from(AssemblerError::DuplicateLabel(
+                slot.name.clone(),
+            )));
+        }
+
+        for (index, label) in &self.jumps {
+            let slot = &self.labels[label.0];
+            let Some(address) = slot.address else {
+                diagnostics.push_with_hint(
+                    RingsError::from(AssemblerError::UnboundLabel(slot.name.clone())),
+                    Some(format!("jumped to by instruction {}", index)),
+                );
+                continue;
+            };
+
+            match &mut self.instructions[*index] {
+                Instruction::JMP(tgt)
+                | Instruction::JEQ(_, _, tgt)
+                | Instruction::JGT(_, _, tgt)
+                | Instruction::JLT(_, _, tgt) => *tgt = address,
+                _ => unreachable!(),
+            }
+        }
+
+        for (index, instruction) in self.instructions.iter().enumerate() {
+            if let Some(target) = instruction.target().filter(|tgt| *tgt > self.address()) {
+                diagnostics.push_with_hint(
+                    RingsError::from(AssemblerError::JumpOutOfRange(target)),
+                    Some(format!("at instruction {}: {}", index, instruction)),
+                );
+            }
+
+            if let Err(e) = instruction.validate() {
+                diagnostics.push_with_hint(
+                    RingsError::from(AssemblerError::from(e)),
+                    Some(format!("at instruction {}: {}", index, instruction)),
+                );
+            }
+        }
+
+        diagnostics.into_result(Program::Unlocalized(self.instructions))
+    }
+}
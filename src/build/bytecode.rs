@@ -0,0 +1,642 @@
+//! Binary serialization of [`Program`]s.
+//!
+//! All integers are little endian.
+//!
+//! ```text
+//! magic        4 bytes  "\0RNB"
+//! version      u8       VERSION
+//! flags        u8       FLAG_DEBUG if the debug section is present
+//! count        u32      number of instructions
+//! instructions          opcode u8, then the operands: u8 for rings and literals, u32 for jump targets
+//! debug section         for each instruction: start line, char and offset, end line, char and offset
+//!                       and source id, all u32; then the number of labels, u32, and for each label
+//!                       its name length u16, the UTF-8 name and its address u32; then the number of
+//!                       source files, u32, and for each file its path length u16 and the UTF-8 path,
+//!                       indexed by source id; then the number of instructions expanded from macros,
+//!                       u32, and for each its address u32 and the number of macro calls it was
+//!                       expanded from, u32, followed by each call, outermost first: its location as
+//!                       for instructions, the macro name length u16 and the UTF-8 name
+//! ```
+//!
+//! Source files are referred to by path only. They are read again when a program is loaded, to
+//! render errors with the offending source line.
+
+use std::{collections::HashMap, io::Read, path::PathBuf};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use super::{CallSites, Program};
+use crate::{
+    instruction::{Instruction, InstructionError, InstructionPrimitive, Label},
+    Localized, Position, SourceId, Sources,
+};
+
+pub const MAGIC: &[u8; 4] = b"\0RNB";
+pub const VERSION: u8 = 2;
+
+const FLAG_DEBUG: u8 = 0b0000_0001;
+
+/// Instruction primitives, indexed by opcode
+const OPCODES: [InstructionPrimitive; 17] = [
+    InstructionPrimitive::MKR,
+    InstructionPrimitive::PUT,
+    InstructionPrimitive::ROT,
+    InstructionPrimitive::SWP,
+    InstructionPrimitive::INP,
+    InstructionPrimitive::INF,
+    InstructionPrimitive::OUT,
+    InstructionPrimitive::ERR,
+    InstructionPrimitive::ADD,
+    InstructionPrimitive::SUB,
+    InstructionPrimitive::MUL,
+    InstructionPrimitive::DIV,
+    InstructionPrimitive::JMP,
+    InstructionPrimitive::JEQ,
+    InstructionPrimitive::JGT,
+    InstructionPrimitive::JLT,
+    InstructionPrimitive::HLT,
+];
+
+pub type BytecodeResult<T> = Result<T, BytecodeError>;
+#[derive(Debug)]
+pub enum BytecodeError {
+    InvalidMagic,
+    UnsupportedVersion(u8),
+    InvalidOpcode(u8),
+    /// Flags other than the ones defined by this version
+    UnknownFlags(u8),
+    /// Jump target past the end of the program
+    InvalidJumpTarget(Label),
+    /// Label defined past the end of the program
+    InvalidLabelAddress(String, Label),
+    /// Macro call sites of an instruction past the end of the program
+    InvalidCallSiteAddress(Label),
+    InvalidLabelName,
+    InvalidMacroName,
+    InvalidSourcePath,
+    /// Label name longer than the u16 length field allows
+    LabelTooLong(String),
+    /// Source path longer than the u16 length field allows
+    SourcePathTooLong(String),
+    /// Macro name longer than the u16 length field allows
+    MacroNameTooLong(String),
+    /// Number, position or address that does not fit in a u32
+    ValueTooLarge(usize),
+    Validation(InstructionError),
+    UnexpectedEnd,
+    TrailingBytes,
+}
+
+impl std::error::Error for BytecodeError {}
+
+impl From<InstructionError> for BytecodeError {
+    fn from(value: InstructionError) -> Self {
+        Self::Validation(value)
+    }
+}
+
+impl From<std::io::Error> for BytecodeError {
+    fn from(_: std::io::Error) -> Self {
+        // Reading from a slice only fails when it runs out of bytes
+        Self::UnexpectedEnd
+    }
+}
+
+impl std::fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidMagic => write!(f, "Not a Rings bytecode file"),
+            Self::UnsupportedVersion(v) => write!(
+                f,
+                "Unsupported bytecode version {}, expected {}",
+                v, VERSION
+            ),
+            Self::InvalidOpcode(op) => write!(f, "Invalid opcode: 0x{:02X}", op),
+            Self::UnknownFlags(flags) => write!(f, "Unknown bytecode flags: 0b{:08b}", flags),
+            Self::InvalidJumpTarget(tgt) => write!(f, "Jump target {} out of range", tgt),
+            Self::InvalidLabelAddress(name, address) => {
+                write!(f, "Address {} of label {} out of range", address, name)
+            }
+            Self::InvalidCallSiteAddress(address) => {
+                write!(f, "Macro call sites of address {} out of range", address)
+            }
+            Self::InvalidLabelName => write!(f, "Label name is not valid UTF-8"),
+            Self::InvalidMacroName => write!(f, "Macro name is not valid UTF-8"),
+            Self::InvalidSourcePath => write!(f, "Source path is not valid UTF-8"),
+            Self::LabelTooLong(name) => write!(
+                f,
+                "Label name is too long: {} bytes, at most {} allowed",
+                name.len(),
+                u16::MAX
+            ),
+            Self::SourcePathTooLong(path) => write!(
+                f,
+                "Source path is too long: {} bytes, at most {} allowed",
+                path.len(),
+                u16::MAX
+            ),
+            Self::MacroNameTooLong(name) => write!(
+                f,
+                "Macro name is too long: {} bytes, at most {} allowed",
+                name.len(),
+                u16::MAX
+            ),
+            Self::ValueTooLarge(v) => write!(f, "Value {} does not fit in bytecode", v),
+            Self::Validation(e) => write!(f, "Instruction validation error: {}", e),
+            Self::UnexpectedEnd => write!(f, "Unexpected end of bytecode"),
+            Self::TrailingBytes => write!(f, "Unexpected bytes after the end of bytecode"),
+        }
+    }
+}
+
+fn opcode(primitive: InstructionPrimitive) -> u8 {
+    OPCODES.iter().position(|p| *p == primitive).unwrap() as u8
+}
+
+fn write_u32(out: &mut Vec<u8>, value: usize) -> BytecodeResult<()> {
+    let value = u32::try_from(value).map_err(|_| BytecodeError::ValueTooLarge(value))?;
+    out.write_u32::<LittleEndian>(value).unwrap();
+    Ok(())
+}
+
+fn write_instruction(out: &mut Vec<u8>, instruction: &Instruction) -> BytecodeResult<()> {
+    out.push(opcode(instruction.primitive()));
+    match *instruction {
+        Instruction::MKR(a)
+        | Instruction::INP(a)
+        | Instruction::OUT(a)
+        | Instruction::ERR(a)
+        | Instruction::HLT(a) => out.push(a),
+        Instruction::PUT(a, b)
+        | Instruction::ROT(a, b)
+        | Instruction::SWP(a, b)
+        | Instruction::INF(a, b) => out.extend([a, b]),
+        Instruction::ADD(a, b, c)
+        | Instruction::SUB(a, b, c)
+        | Instruction::MUL(a, b, c)
+        | Instruction::DIV(a, b, c) => out.extend([a, b, c]),
+        Instruction::JMP(tgt) => write_u32(out, tgt)?,
+        Instruction::JEQ(a, b, tgt) | Instruction::JGT(a, b, tgt) | Instruction::JLT(a, b, tgt) => {
+            out.extend([a, b]);
+            write_u32(out, tgt)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn read_u32(src: &mut &[u8]) -> BytecodeResult<usize> {
+    Ok(src.read_u32::<LittleEndian>()? as usize)
+}
+
+/// Writes `text` prefixed with its u16 length, or returns `too_long` if it does not fit
+fn write_string(
+    out: &mut Vec<u8>,
+    text: &str,
+    too_long: fn(String) -> BytecodeError,
+) -> BytecodeResult<()> {
+    let length = u16::try_from(text.len()).map_err(|_| too_long(text.to_owned()))?;
+    out.write_u16::<LittleEndian>(length).unwrap();
+    out.extend(text.as_bytes());
+    Ok(())
+}
+
+/// Reads a string written by [`write_string`], or returns `invalid` if it is not UTF-8
+fn read_string(src: &mut &[u8], invalid: BytecodeError) -> BytecodeResult<String> {
+    let mut text = vec![0; src.read_u16::<LittleEndian>()? as usize];
+    src.read_exact(&mut text)?;
+    String::from_utf8(text).map_err(|_| invalid)
+}
+
+fn read_instruction(src: &mut &[u8]) -> BytecodeResult<Instruction> {
+    let op = src.read_u8()?;
+    let primitive = *OPCODES
+        .get(op as usize)
+        .ok_or(BytecodeError::InvalidOpcode(op))?;
+
+    macro_rules! u8 {
+        () => {
+            src.read_u8()
+        };
+    }
+
+    let instruction = match primitive {
+        InstructionPrimitive::MKR => Instruction::MKR(u8!()?),
+        InstructionPrimitive::PUT => Instruction::PUT(u8!()?, u8!()?),
+        InstructionPrimitive::ROT => Instruction::ROT(u8!()?, u8!()?),
+        InstructionPrimitive::SWP => Instruction::SWP(u8!()?, u8!()?),
+        InstructionPrimitive::INP => Instruction::INP(u8!()?),
+        InstructionPrimitive::INF => Instruction::INF(u8!()?, u8!()?),
+        InstructionPrimitive::OUT => Instruction::OUT(u8!()?),
+        InstructionPrimitive::ERR => Instruction::ERR(u8!()?),
+        InstructionPrimitive::ADD => Instruction::ADD(u8!()?, u8!()?, u8!()?),
+        InstructionPrimitive::SUB => Instruction::SUB(u8!()?, u8!()?, u8!()?),
+        InstructionPrimitive::MUL => Instruction::MUL(u8!()?, u8!()?, u8!()?),
+        InstructionPrimitive::DIV => Instruction::DIV(u8!()?, u8!()?, u8!()?),
+        InstructionPrimitive::HLT => Instruction::HLT(u8!()?),
+        InstructionPrimitive::JMP => Instruction::JMP(read_u32(src)?),
+        InstructionPrimitive::JEQ => Instruction::JEQ(u8!()?, u8!()?, read_u32(src)?),
+        InstructionPrimitive::JGT => Instruction::JGT(u8!()?, u8!()?, read_u32(src)?),
+        InstructionPrimitive::JLT => Instruction::JLT(u8!()?, u8!()?, read_u32(src)?),
+    };
+
+    instruction.validate()?;
+    Ok(instruction)
+}
+
+fn read_position(src: &mut &[u8]) -> BytecodeResult<Position> {
+    Ok(Position {
+        line_number: read_u32(src)?,
+        char_number: read_u32(src)?,
+        offset: read_u32(src)?,
+    })
+}
+
+fn write_position(out: &mut Vec<u8>, position: Position) -> BytecodeResult<()> {
+    write_u32(out, position.line_number)?;
+    write_u32(out, position.char_number)?;
+    write_u32(out, position.offset)
+}
+
+/// Writes the start, end and source id of `location`
+fn write_location<V>(out: &mut Vec<u8>, location: &Localized<V>) -> BytecodeResult<()> {
+    write_position(out, location.start())?;
+    write_position(out, location.end)?;
+    write_u32(out, location.source.0)
+}
+
+/// Reads a location written by [`write_location`], holding `value`
+fn read_location<V>(src: &mut &[u8], value: V) -> BytecodeResult<Localized<V>> {
+    let start = read_position(src)?;
+    let end = read_position(src)?;
+    let source = SourceId(read_u32(src)?);
+    Ok(Localized::at(start, source, value).with_end(end))
+}
+
+impl Program {
+    /// Whether `bytes` start with the bytecode magic header
+    pub fn is_bytecode(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Serializes the program. Localized programs include a debug section
+    /// with the instruction locations, label names and the paths of `sources`.
+    pub fn to_bytes(&self, sources: &Sources) -> BytecodeResult<Vec<u8>> {
+        let mut out = Vec::from(&MAGIC[..]);
+        out.push(VERSION);
+
+        match self {
+            Self::Unlocalized(instructions) => {
+                out.push(0);
+                write_u32(&mut out, instructions.len())?;
+                for instruction in instructions {
+                    write_instruction(&mut out, instruction)?;
+                }
+            }
+            Self::Localized(instructions, labels, call_sites) => {
+                out.push(FLAG_DEBUG);
+                write_u32(&mut out, instructions.len())?;
+                for instruction in instructions {
+                    write_instruction(&mut out, instruction)?;
+                }
+
+                for instruction in instructions {
+                    write_location(&mut out, instruction)?;
+                }
+
+                // Sorted, so that the same program always serializes to the same bytes
+                let mut labels: Vec<_> = labels.iter().collect();
+                labels.sort_by_key(|(name, address)| (**address, *name));
+
+                write_u32(&mut out, labels.len())?;
+                for (name, address) in labels {
+                    write_string(&mut out, name, BytecodeError::LabelTooLong)?;
+                    write_u32(&mut out, *address)?;
+                }
+
+                let paths: Vec<_> = sources.iter().map(|(_, file)| &file.path).collect();
+                write_u32(&mut out, paths.len())?;
+                for path in paths {
+                    let path = path.to_string_lossy();
+                    write_string(&mut out, &path, BytecodeError::SourcePathTooLong)?;
+                }
+
+                let mut call_sites: Vec<_> = call_sites.iter().collect();
+                call_sites.sort_by_key(|(address, _)| **address);
+
+                write_u32(&mut out, call_sites.len())?;
+                for (address, calls) in call_sites {
+                    write_u32(&mut out, *address)?;
+                    write_u32(&mut out, calls.len())?;
+                    for call in calls {
+                        write_location(&mut out, call)?;
+                        write_string(&mut out, call, BytecodeError::MacroNameTooLong)?;
+                    }
+                }
+            }
+        }
+
+        Ok(out)
+    }
+
+    /// Deserializes a program. The source files named in the debug section are added to
+    /// `sources`, with their current contents or empty if they cannot be read.
+    pub fn from_bytes(bytes: &[u8], sources: &mut Sources) -> BytecodeResult<Self> {
+        if !Self::is_bytecode(bytes) {
+            return Err(BytecodeError::InvalidMagic);
+        }
+
+        let mut src = &bytes[MAGIC.len()..];
+        let version = src.read_u8()?;
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+
+        let flags = src.read_u8()?;
+        if flags & !FLAG_DEBUG != 0 {
+            return Err(BytecodeError::UnknownFlags(flags));
+        }
+        let count = read_u32(&mut src)?;
+
+        // Not preallocated, the count has not been checked against the length of the input yet
+        let mut instructions = Vec::new();
+        for _ in 0..count {
+            instructions.push(read_instruction(&mut src)?);
+        }
+
+        let mut targets = instructions.iter().filter_map(Instruction::target);
+        if let Some(tgt) = targets.find(|tgt| *tgt > instructions.len()) {
+            return Err(BytecodeError::InvalidJumpTarget(tgt));
+        }
+
+        let mut paths = Vec::new();
+        let program = if flags & FLAG_DEBUG == 0 {
+            Self::Unlocalized(instructions)
+        } else {
+            let mut localized = Vec::with_capacity(instructions.len());
+            for instruction in instructions {
+                localized.push(read_location(&mut src, instruction)?);
+            }
+
+            let mut labels = HashMap::new();
+            for _ in 0..read_u32(&mut src)? {
+                let name = read_string(&mut src, BytecodeError::InvalidLabelName)?;
+                let address = read_u32(&mut src)?;
+                if address > localized.len() {
+                    return Err(BytecodeError::InvalidLabelAddress(name, address));
+                }
+                labels.insert(name, address);
+            }
+
+            for _ in 0..read_u32(&mut src)? {
+                paths.push(read_string(&mut src, BytecodeError::InvalidSourcePath)?);
+            }
+
+            let mut call_sites = HashMap::new();
+            for _ in 0..read_u32(&mut src)? {
+                let address = read_u32(&mut src)?;
+                if address >= localized.len() {
+                    return Err(BytecodeError::InvalidCallSiteAddress(address));
+                }
+
+                let mut calls = CallSites::new();
+                for _ in 0..read_u32(&mut src)? {
+                    let call = read_location(&mut src, ())?;
+                    let name = read_string(&mut src, BytecodeError::InvalidMacroName)?;
+                    calls.push(call.transform(name));
+                }
+                call_sites.insert(address, calls);
+            }
+
+            Self::Localized(localized, labels, call_sites)
+        };
+
+        if !src.is_empty() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+
+        for path in paths.into_iter().map(PathBuf::from) {
+            let text = std::fs::read_to_string(&path).unwrap_or_default();
+            sources.add(path, text);
+        }
+
+        Ok(program)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{BytecodeError, FLAG_DEBUG, MAGIC, VERSION};
+    use crate::{
+        build::{Program, ProgramAssembler},
+        Localized, Sources,
+    };
+
+    const SOURCE: &str = "mkr 1\n:loop inp 0\nout 0\njgt 0 0 :loop\nhlt 3\n";
+
+    fn bytes(preserve_location: bool) -> Vec<u8> {
+        let program = ProgramAssembler::assemble(SOURCE.as_bytes(), preserve_location).unwrap();
+        program.to_bytes(&Sources::new()).unwrap()
+    }
+
+    #[test]
+    fn round_trip_without_debug_section() {
+        let bytes = bytes(false);
+        assert_eq!(bytes[MAGIC.len() + 1] & FLAG_DEBUG, 0);
+
+        let Program::Unlocalized(instructions) =
+            Program::from_bytes(&bytes, &mut Sources::new()).unwrap()
+        else {
+            panic!("expected a program without locations");
+        };
+        let instructions: Vec<_> = instructions.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            instructions,
+            ["mkr 1", "inp 0", "out 0", "jgt 0 0 :L1", "hlt 3"]
+        );
+        assert_eq!(
+            Program::from_bytes(&bytes, &mut Sources::new())
+                .unwrap()
+                .to_bytes(&Sources::new())
+                .unwrap(),
+            bytes
+        );
+    }
+
+    #[test]
+    fn round_trip_with_debug_section() {
+        let bytes = bytes(true);
+        assert_eq!(bytes[MAGIC.len() + 1] & FLAG_DEBUG, FLAG_DEBUG);
+
+        let program = Program::from_bytes(&bytes, &mut Sources::new()).unwrap();
+        assert_eq!(program.to_bytes(&Sources::new()).unwrap(), bytes);
+
+        let Program::Localized(instructions, labels, _) = program else {
+            panic!("expected a program with locations");
+        };
+        assert_eq!(labels, HashMap::from([("loop".to_owned(), 1)]));
+        let locations: Vec<_> = instructions
+            .iter()
+            .map(|i| (i.line_number, i.char_number, i.end.char_number))
+            .collect();
+        assert_eq!(
+            locations,
+            [(1, 1, 6), (2, 7, 12), (3, 1, 6), (4, 1, 14), (5, 1, 6)]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_input() {
+        for preserve_location in [false, true] {
+            let bytes = bytes(preserve_location);
+            for length in MAGIC.len()..bytes.len() {
+                assert!(matches!(
+                    Program::from_bytes(&bytes[..length], &mut Sources::new()),
+                    Err(BytecodeError::UnexpectedEnd)
+                ));
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_magic() {
+        let mut bytes = bytes(false);
+        bytes[1] = b'X';
+        assert!(matches!(
+            Program::from_bytes(&bytes, &mut Sources::new()),
+            Err(BytecodeError::InvalidMagic)
+        ));
+        assert!(matches!(
+            Program::from_bytes(SOURCE.as_bytes(), &mut Sources::new()),
+            Err(BytecodeError::InvalidMagic)
+        ));
+    }
+
+    #[test]
+    fn rejects_trailing_bytes() {
+        for preserve_location in [false, true] {
+            let mut bytes = bytes(preserve_location);
+            bytes.push(0);
+            assert!(matches!(
+                Program::from_bytes(&bytes, &mut Sources::new()),
+                Err(BytecodeError::TrailingBytes)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_unsupported_version() {
+        let mut bytes = bytes(false);
+        bytes[MAGIC.len()] = VERSION + 1;
+        assert!(matches!(
+            Program::from_bytes(&bytes, &mut Sources::new()),
+            Err(BytecodeError::UnsupportedVersion(v)) if v == VERSION + 1
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_opcode() {
+        let mut bytes = bytes(false);
+        // The opcode of the first instruction, after the magic, version, flags and count
+        bytes[MAGIC.len() + 6] = 0xFF;
+        assert!(matches!(
+            Program::from_bytes(&bytes, &mut Sources::new()),
+            Err(BytecodeError::InvalidOpcode(0xFF))
+        ));
+    }
+
+    #[test]
+    fn rejects_long_label_names() {
+        let name = "a".repeat(u16::MAX as usize + 1);
+        let program = Program::Localized(Vec::new(), HashMap::from([(name, 0)]), HashMap::new());
+        assert!(matches!(
+            program.to_bytes(&Sources::new()),
+            Err(BytecodeError::LabelTooLong(..))
+        ));
+    }
+
+    #[test]
+    fn rejects_unknown_flags() {
+        let mut bytes = bytes(false);
+        bytes[MAGIC.len() + 1] = 0b1000_0000;
+        assert!(matches!(
+            Program::from_bytes(&bytes, &mut Sources::new()),
+            Err(BytecodeError::UnknownFlags(0b1000_0000))
+        ));
+    }
+
+    #[test]
+    fn rejects_label_addresses_past_the_end() {
+        let mut bytes = bytes(true);
+        // The address of the only label is followed by the empty lists of sources and call sites
+        let address = bytes.len() - 12;
+        bytes[address] = 6;
+        assert!(matches!(
+            Program::from_bytes(&bytes, &mut Sources::new()),
+            Err(BytecodeError::InvalidLabelAddress(name, 6)) if name == "loop"
+        ));
+    }
+
+    #[test]
+    fn round_trip_with_macro_call_sites() {
+        let source = ".macro put3 r\nput r 3\n.endm\n.macro twice r\nput3 r\nput3 r\n.endm\nmkr 1\ntwice 0\n";
+        let program = ProgramAssembler::assemble(source.as_bytes(), true).unwrap();
+        let bytes = program.to_bytes(&Sources::new()).unwrap();
+
+        let program = Program::from_bytes(&bytes, &mut Sources::new()).unwrap();
+        assert_eq!(program.to_bytes(&Sources::new()).unwrap(), bytes);
+        let calls: Vec<_> = (0..program.len())
+            .map(|address| {
+                let calls = program.call_sites(address).iter();
+                calls
+                    .map(|call| (call.line_number, call.value.clone()))
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let expected = vec![(9, "twice".to_owned()), (5, "put3".to_owned())];
+        assert_eq!(calls[0], []);
+        assert_eq!(calls[1], expected);
+        assert_eq!(calls[2][1], (6, "put3".to_owned()));
+    }
+
+    #[test]
+    fn rejects_call_sites_past_the_end() {
+        let call = Localized::new("twice".to_owned());
+        let call_sites = HashMap::from([(1, vec![call])]);
+        let program = Program::Localized(Vec::new(), HashMap::new(), call_sites);
+        let bytes = program.to_bytes(&Sources::new()).unwrap();
+        assert!(matches!(
+            Program::from_bytes(&bytes, &mut Sources::new()),
+            Err(BytecodeError::InvalidCallSiteAddress(1))
+        ));
+    }
+
+    #[test]
+    fn reads_source_files_again() {
+        let dir = std::env::temp_dir().join(format!("rings-bytecode-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("program.rn");
+        std::fs::write(&path, SOURCE).unwrap();
+
+        let mut sources = Sources::new();
+        let program =
+            ProgramAssembler::assemble_file(&path, SOURCE.as_bytes(), &[], true, &mut sources)
+                .unwrap();
+        let bytes = program.to_bytes(&sources).unwrap();
+
+        let mut loaded = Sources::new();
+        let program = Program::from_bytes(&bytes, &mut loaded).unwrap();
+        assert_eq!(program.to_bytes(&loaded).unwrap(), bytes);
+        let files: Vec<_> = loaded.iter().map(|(_, f)| (&f.path, &f.text[..])).collect();
+        assert_eq!(files, [(&path, SOURCE)]);
+
+        // Files that cannot be read anymore keep their name, for the error location
+        std::fs::remove_dir_all(dir).unwrap();
+        let mut loaded = Sources::new();
+        Program::from_bytes(&bytes, &mut loaded).unwrap();
+        let files: Vec<_> = loaded.iter().map(|(_, f)| (&f.path, &f.text[..])).collect();
+        assert_eq!(files, [(&path, "")]);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{error::LocalizedRingsResult, Localized};
+use crate::{error::LocalizedRingsResult, Localized, Position, SourceId};
 
 type CharacterReaderResult<T> = Result<T, CharacterReaderError>;
 #[derive(Debug)]
@@ -28,7 +28,8 @@ impl std::fmt::Display for CharacterReaderError {
 
 pub struct CharIterator<R> {
     reader: R,
-    location: Localized<()>,
+    position: Position,
+    source: SourceId,
     done: bool,
     read_buffer: [u8; 1],
 }
@@ -38,9 +39,15 @@ where
     R: std::io::Read,
 {
     pub fn new(reader: R) -> Self {
+        Self::with_source(reader, SourceId::default())
+    }
+
+    /// Iterator whose locations refer to the given source file
+    pub fn with_source(reader: R, source: SourceId) -> Self {
         Self {
             reader,
-            location: Localized::new(()),
+            position: Position::default(),
+            source,
             done: false,
             read_buffer: [0u8; 1],
         }
@@ -102,10 +109,7 @@ where
         };
 
         codepoint |= ((first_byte & first_part_pattern) as u32) << ((codepoint_length - 1) * 6);
-        Some(
-            char::from_u32(codepoint)
-                .ok_or_else(|| CharacterReaderError::InvalidCharacter(codepoint)),
-        )
+        Some(char::from_u32(codepoint).ok_or(CharacterReaderError::InvalidCharacter(codepoint)))
     }
 }
 
@@ -131,20 +135,16 @@ where
         match self.read_char() {
             None => {
                 self.done = true;
-                Some(self.location.transform(Ok('\n')))
+                Some(Localized::at(self.position, self.source, Ok('\n')))
             }
             Some(Ok(c)) => {
-                let ret = Some(self.location.transform(Ok(c)));
-                if c == '\n' {
-                    self.location.new_line();
-                } else {
-                    self.location.inc_char();
-                }
-                ret
+                let start = self.position;
+                self.position.advance(c);
+                Some(Localized::at(start, self.source, Ok(c)).with_end(self.position))
             }
             Some(Err(e)) => {
                 self.done = true;
-                Some(self.location.transform(Err(e.into())))
+                Some(Localized::at(self.position, self.source, Err(e.into())))
             }
         }
     }
@@ -0,0 +1,338 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt::Display,
+};
+
+use super::{labels, Program};
+use crate::instruction::{Instruction, Label};
+
+/// A label as printed by the disassembler
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Name {
+    Global(String),
+    /// A local label such as `.loop`, with the global label it belongs to
+    Local {
+        scope: String,
+        name: String,
+    },
+}
+
+impl Name {
+    /// The name a jump from an instruction in `scope` refers to the label with
+    fn seen_from(&self, scope: Option<&str>) -> String {
+        match self {
+            Self::Global(name) => name.clone(),
+            Self::Local { scope: s, name } if Some(s.as_str()) == scope => name.clone(),
+            Self::Local { scope, name } => format!("{}{}", scope, name),
+        }
+    }
+
+    /// The name a jump from any scope refers to the label with
+    fn full_name(&self) -> String {
+        self.seen_from(None)
+    }
+}
+
+/// Labels of a disassembled program, with the scope of local labels at each address
+struct LabelNames {
+    /// Labels defined at each address, in the order they are printed
+    definitions: BTreeMap<Label, Vec<Name>>,
+    /// The label jumps to each address refer to
+    targets: HashMap<Label, Name>,
+    /// The global label in effect at each address
+    scopes: Vec<Option<String>>,
+}
+
+impl LabelNames {
+    /// Text of the jump target `target` in an instruction at `address`
+    fn target(&self, address: Label, target: Label) -> String {
+        let scope = self.scopes.get(address).cloned().flatten();
+        self.targets[&target].seen_from(scope.as_deref())
+    }
+}
+
+impl Program {
+    /// The names labels are printed with: names as written in the source, where they are known
+    /// and unambiguous. Labels of macro expansions are printed with their name in the macro body.
+    fn source_labels(&self) -> HashMap<Label, Vec<Name>> {
+        let Some(labels) = self.labels() else {
+            return HashMap::new();
+        };
+
+        let mut names = Vec::new();
+        for (label, address) in labels {
+            let name = labels::source_name(label);
+            // Anonymous labels are matched by position, `:-` and `:+` cannot be printed as such.
+            // Local labels without a global label before them cannot be jumped to from elsewhere.
+            if labels::is_anonymous(name) || name.starts_with('.') {
+                continue;
+            }
+
+            let local = match label.rsplit_once('.') {
+                Some((scope, local)) if !label.contains('@') && labels.contains_key(scope) => {
+                    Some(Name::Local {
+                        scope: scope.to_owned(),
+                        name: format!(".{}", local),
+                    })
+                }
+                _ => None,
+            };
+            names.push((
+                *address,
+                local.unwrap_or_else(|| Name::Global(name.to_owned())),
+            ));
+        }
+
+        // Labels of different expansions of a macro share their name in the macro body
+        let mut count: HashMap<&Name, usize> = HashMap::new();
+        for (_, name) in &names {
+            *count.entry(name).or_default() += 1;
+        }
+        let unique: Vec<_> = names
+            .iter()
+            .filter(|(_, name)| count[name] == 1)
+            .cloned()
+            .collect();
+
+        let mut by_address: HashMap<Label, Vec<Name>> = HashMap::new();
+        for (address, name) in unique {
+            by_address.entry(address).or_default().push(name);
+        }
+
+        by_address
+    }
+
+    /// Label names to print at each address. Original names are kept where they can be printed,
+    /// other jump targets get a generated `L<address>` label, local to the current scope if there
+    /// is one.
+    fn label_names(&self) -> LabelNames {
+        let mut source_labels = self.source_labels();
+        let mut taken: HashSet<String> = source_labels
+            .values()
+            .flatten()
+            .map(|name| name.full_name())
+            .collect();
+
+        let targets: HashSet<_> = (0..self.len())
+            .filter_map(|i| self.get(i).and_then(|instruction| instruction.target()))
+            .collect();
+
+        let mut names = LabelNames {
+            definitions: BTreeMap::new(),
+            targets: HashMap::new(),
+            scopes: Vec::with_capacity(self.len() + 1),
+        };
+        let mut scope: Option<String> = None;
+
+        for address in 0..=self.len() {
+            let mut here = source_labels.remove(&address).unwrap_or_default();
+            here.sort_by_key(Name::full_name);
+            let is_scope = |name: &Name, scope: &Option<String>| match name {
+                Name::Local { scope: s, .. } => Some(s) == scope.as_ref(),
+                Name::Global(..) => false,
+            };
+
+            // Local labels of the current scope first, then the global labels, the one the
+            // remaining local labels belong to last, and then those local labels
+            let mut definitions: Vec<Name> = Vec::new();
+            let (mut locals, globals): (Vec<_>, Vec<_>) = here
+                .into_iter()
+                .partition(|name| matches!(name, Name::Local { .. }));
+            locals.retain(|name| {
+                let current = is_scope(name, &scope);
+                if current {
+                    definitions.push(name.clone());
+                }
+                !current
+            });
+
+            let mut globals: Vec<String> =
+                globals.into_iter().map(|name| name.full_name()).collect();
+            globals.sort_by_key(|global| {
+                let global = Some(global.clone());
+                locals.iter().any(|local| is_scope(local, &global))
+            });
+            for global in globals {
+                scope = Some(global.clone());
+                definitions.push(Name::Global(global));
+            }
+
+            // Local labels of another scope would change their meaning, they are left out
+            definitions.extend(locals.into_iter().filter(|name| is_scope(name, &scope)));
+
+            if definitions.is_empty() && targets.contains(&address) {
+                let mut generated = format!("L{}", address);
+                let full_name = |generated: &str| match &scope {
+                    Some(scope) => format!("{}.{}", scope, generated),
+                    None => generated.to_owned(),
+                };
+                while taken.contains(&full_name(&generated)) {
+                    generated.push('_');
+                }
+                taken.insert(full_name(&generated));
+
+                let name = match &scope {
+                    Some(s) => Name::Local {
+                        scope: s.clone(),
+                        name: format!(".{}", generated),
+                    },
+                    None => {
+                        scope = Some(generated.clone());
+                        Name::Global(generated)
+                    }
+                };
+                definitions.push(name);
+            }
+
+            // Jumps prefer global labels, which read the same from every scope
+            let target = definitions
+                .iter()
+                .find(|name| matches!(name, Name::Global(..)))
+                .or(definitions.first());
+            if let Some(target) = target {
+                names.targets.insert(address, target.clone());
+            }
+            if !definitions.is_empty() {
+                names.definitions.insert(address, definitions);
+            }
+            names.scopes.push(scope.clone());
+        }
+
+        names
+    }
+
+    /// Writes the instruction at `address` in canonical syntax, with jump targets named as in
+    /// the disassembly. Returns `None` past the end of the program.
+    pub fn instruction_text(&self, address: Label) -> Option<String> {
+        let instruction = self.get(address)?;
+        let names = self.label_names();
+        let mut text = String::new();
+        let instruction: &Instruction = instruction.as_ref();
+        instruction
+            .write_with_labels(&mut text, |tgt| names.target(address, tgt))
+            .ok()?;
+        Some(text)
+    }
+
+    /// Regenerates Rings source from the program, see the [`Display`] implementation
+    pub fn disassemble(&self) -> String {
+        self.to_string()
+    }
+}
+
+/// Rings source in canonical syntax: labels at column 0, instructions indented by 4 spaces.
+/// Labels keep their names from the source where they are known. Jumps refer to the first
+/// global label at their target, or to a local label as seen from the scope of the jump.
+impl Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names = self.label_names();
+
+        for address in 0..=self.len() {
+            if let Some(labels) = names.definitions.get(&address) {
+                if address > 0 {
+                    writeln!(f)?;
+                }
+
+                for label in labels {
+                    let label = match label {
+                        Name::Global(name) | Name::Local { name, .. } => name,
+                    };
+                    writeln!(f, ":{}", label)?;
+                }
+            }
+
+            let Some(instruction) = self.get(address) else {
+                break;
+            };
+
+            write!(f, "    ")?;
+            let instruction: &Instruction = instruction.as_ref();
+            instruction.write_with_labels(f, |tgt| names.target(address, tgt))?;
+            writeln!(f)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build::ProgramAssembler;
+
+    fn disassemble(source: &str, preserve_location: bool) -> String {
+        ProgramAssembler::assemble(source.as_bytes(), preserve_location)
+            .unwrap()
+            .disassemble()
+    }
+
+    #[test]
+    fn prints_source_names() {
+        let source = "\
+.macro twice r
+:again
+    jeq r r :again
+.endm
+.macro once r
+:done
+    jmp :done
+.endm
+:main
+    mkr 1
+:.loop
+    twice 0
+    twice 0
+    once 0
+:-
+    jeq 0 0 :+
+    jmp :.loop
+:+
+    jmp :-
+:other
+    jmp :main.loop";
+        let expected = "\
+:main
+    mkr 1
+
+:.loop
+    jeq 0 0 :.loop
+
+:.L2
+    jeq 0 0 :.L2
+
+:done
+    jmp :done
+
+:.L4
+    jeq 0 0 :.L6
+    jmp :main.loop
+
+:.L6
+    jmp :.L4
+
+:other
+    jmp :main.loop
+";
+        assert_eq!(disassemble(source, true), expected);
+        assert_eq!(disassemble(expected, true), expected);
+    }
+
+    #[test]
+    fn generates_names_without_debug_section() {
+        let source = "\
+:start
+    mkr 1
+:.loop
+    jmp :.loop
+    jmp :start";
+        let expected = "\
+:L0
+    mkr 1
+
+:.L1
+    jmp :.L1
+    jmp :L0
+";
+        assert_eq!(disassemble(source, false), expected);
+        assert_eq!(disassemble(expected, false), expected);
+    }
+}
@@ -0,0 +1,370 @@
+//! Constant expressions in instruction arguments and directives, e.g. `SIZE-1`, `'A'+1` or `0x10|3`.
+//!
+//! Operators, from the lowest precedence: `|`, `^`, `&`, `<<` and `>>`, `+` and `-`, `*`, `/` and `%`,
+//! unary `-`. Operands are numbers in any number system, character literals and names of constants.
+//! Expressions are evaluated on 64-bit integers and the result has to fit in a byte.
+
+use std::{iter::Peekable, str::Chars};
+
+use super::token::unescape;
+
+pub type ExpressionResult<T> = Result<T, ExpressionError>;
+#[derive(Debug)]
+pub enum ExpressionError {
+    InvalidExpression(String),
+    UnknownName(String),
+    DivisionByZero,
+    Overflow,
+    /// The result does not fit in a byte
+    OutOfRange(i64),
+}
+
+impl std::error::Error for ExpressionError {}
+
+impl std::fmt::Display for ExpressionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidExpression(e) => write!(f, "Invalid expression: {}", e),
+            Self::UnknownName(name) => write!(f, "Unknown constant: {}", name),
+            Self::DivisionByZero => write!(f, "Division by zero in expression"),
+            Self::Overflow => write!(f, "Overflow in expression"),
+            Self::OutOfRange(v) => write!(f, "Value {} out of range 0-255", v),
+        }
+    }
+}
+
+/// Whether `s` is a plain name rather than an expression
+pub fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_')
+        && chars.all(|c| c.is_alphanumeric() || c == '_')
+}
+
+struct Parser<'a, L> {
+    expression: &'a str,
+    chars: Peekable<Chars<'a>>,
+    lookup: L,
+}
+
+impl<'a, L> Parser<'a, L>
+where
+    L: Fn(&str) -> Option<u8>,
+{
+    fn invalid(&self) -> ExpressionError {
+        ExpressionError::InvalidExpression(self.expression.to_owned())
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.chars.next_if_eq(&c).is_some()
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let rest: String = self.chars.clone().take(s.len()).collect();
+        if rest != s {
+            return false;
+        }
+
+        for _ in 0..s.len() {
+            self.chars.next();
+        }
+        true
+    }
+
+    /// Parses one level of left-associative binary operators
+    fn binary<N, O>(&mut self, next: N, operators: &[&str], apply: O) -> ExpressionResult<i64>
+    where
+        N: Fn(&mut Self) -> ExpressionResult<i64>,
+        O: Fn(&str, i64, i64) -> Option<ExpressionResult<i64>>,
+    {
+        let mut value = next(self)?;
+        'outer: loop {
+            for operator in operators {
+                if self.eat_str(operator) {
+                    let rhs = next(self)?;
+                    value = apply(operator, value, rhs).ok_or(ExpressionError::Overflow)??;
+                    continue 'outer;
+                }
+            }
+
+            return Ok(value);
+        }
+    }
+
+    fn or(&mut self) -> ExpressionResult<i64> {
+        self.binary(Self::xor, &["|"], |_, a, b| Some(Ok(a | b)))
+    }
+
+    fn xor(&mut self) -> ExpressionResult<i64> {
+        self.binary(Self::and, &["^"], |_, a, b| Some(Ok(a ^ b)))
+    }
+
+    fn and(&mut self) -> ExpressionResult<i64> {
+        self.binary(Self::shift, &["&"], |_, a, b| Some(Ok(a & b)))
+    }
+
+    fn shift(&mut self) -> ExpressionResult<i64> {
+        self.binary(Self::sum, &["<<", ">>"], |op, a, b| {
+            let b = u32::try_from(b).ok()?;
+            match op {
+                "<<" => a.checked_shl(b).filter(|v| v >> b == a).map(Ok),
+                _ => Some(Ok(a.checked_shr(b).unwrap_or_default())),
+            }
+        })
+    }
+
+    fn sum(&mut self) -> ExpressionResult<i64> {
+        self.binary(Self::product, &["+", "-"], |op, a, b| match op {
+            "+" => a.checked_add(b).map(Ok),
+            _ => a.checked_sub(b).map(Ok),
+        })
+    }
+
+    fn product(&mut self) -> ExpressionResult<i64> {
+        self.binary(Self::unary, &["*", "/", "%"], |op, a, b| match (op, b) {
+            ("*", _) => a.checked_mul(b).map(Ok),
+            (_, 0) => Some(Err(ExpressionError::DivisionByZero)),
+            ("/", _) => a.checked_div(b).map(Ok),
+            _ => a.checked_rem(b).map(Ok),
+        })
+    }
+
+    fn unary(&mut self) -> ExpressionResult<i64> {
+        if self.eat('-') {
+            return self.unary()?.checked_neg().ok_or(ExpressionError::Overflow);
+        }
+
+        self.primary()
+    }
+
+    fn primary(&mut self) -> ExpressionResult<i64> {
+        match self.chars.peek() {
+            Some('(') => {
+                self.chars.next();
+                let value = self.or()?;
+                if !self.eat(')') {
+                    return Err(self.invalid());
+                }
+                Ok(value)
+            }
+            Some('\'') => self.character(),
+            Some('0'..='9') => self.number(),
+            Some(c) if c.is_alphabetic() || *c == '_' => self.name(),
+            _ => Err(self.invalid()),
+        }
+    }
+
+    fn character(&mut self) -> ExpressionResult<i64> {
+        self.chars.next();
+        let value = match self.chars.next() {
+            Some('\\') => unescape(&mut self.chars).ok_or_else(|| self.invalid())?,
+            Some(c) if c != '\'' && c.is_ascii() => c as u8,
+            _ => return Err(self.invalid()),
+        };
+
+        if !self.eat('\'') {
+            return Err(self.invalid());
+        }
+        Ok(i64::from(value))
+    }
+
+    fn number(&mut self) -> ExpressionResult<i64> {
+        let mut digits = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_ascii_alphanumeric()) {
+            digits.push(c);
+        }
+
+        let (radix, digits) = match digits.as_bytes() {
+            [b'0', b'x' | b'X', ..] => (16, &digits[2..]),
+            [b'0', b'b', ..] => (2, &digits[2..]),
+            [b'0', _, ..] => (8, &digits[1..]),
+            _ => (10, &digits[..]),
+        };
+
+        i64::from_str_radix(digits, radix).map_err(|_| self.invalid())
+    }
+
+    fn name(&mut self) -> ExpressionResult<i64> {
+        let mut name = String::new();
+        while let Some(c) = self.chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+            name.push(c);
+        }
+
+        (self.lookup)(&name)
+            .map(i64::from)
+            .ok_or(ExpressionError::UnknownName(name))
+    }
+}
+
+/// Evaluates `expression`, resolving names with `lookup`
+pub fn evaluate<L>(expression: &str, lookup: L) -> ExpressionResult<u8>
+where
+    L: Fn(&str) -> Option<u8>,
+{
+    let mut parser = Parser {
+        expression,
+        chars: expression.chars().peekable(),
+        lookup,
+    };
+
+    let value = parser.or()?;
+    if parser.chars.next().is_some() {
+        return Err(parser.invalid());
+    }
+
+    u8::try_from(value).map_err(|_| ExpressionError::OutOfRange(value))
+}
+
+/// Replaces the names in `expression` for which `replace` returns a text, leaving numbers and
+/// character literals untouched
+pub fn replace_names<F>(expression: &str, replace: F) -> String
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut out = String::with_capacity(expression.len());
+    let mut chars = expression.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                out.push(c);
+                while let Some(c) = chars.next() {
+                    out.push(c);
+                    match c {
+                        '\\' => out.extend(chars.next()),
+                        '\'' => break,
+                        _ => (),
+                    }
+                }
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut word = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_') {
+                    word.push(c);
+                }
+
+                // Numbers like 0x1F are words too
+                let text = if is_name(&word) { replace(&word) } else { None };
+                out.push_str(text.as_deref().unwrap_or(&word));
+            }
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{evaluate, replace_names, ExpressionError};
+
+    /// Whether an error is the expected one
+    type Expected = fn(&ExpressionError) -> bool;
+
+    fn lookup(name: &str) -> Option<u8> {
+        match name {
+            "SIZE" => Some(16),
+            "max" => Some(255),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn evaluates_valid_expressions() {
+        let cases = [
+            // Operands
+            ("42", 42),
+            ("0x1F", 31),
+            ("0b101", 5),
+            ("017", 15),
+            ("'A'", 65),
+            ("'\\n'", 10),
+            ("SIZE", 16),
+            // Precedence, from the highest
+            ("-1+2", 1),
+            ("2+3*4", 14),
+            ("7-6/2", 4),
+            ("1+7%4", 4),
+            ("1<<2+1", 8),
+            ("0x10>>1+1", 4),
+            ("6&3<<1", 6),
+            ("1^3&1", 0),
+            ("4|1^1", 4),
+            ("(2+3)*4", 20),
+            ("SIZE-1", 15),
+            ("'A'+1", 66),
+            // Left associativity
+            ("10-3-2", 5),
+            ("64/4/2", 8),
+            // Unary minus
+            ("--5", 5),
+            ("-(-200)", 200),
+            ("3*-1+4", 1),
+            ("-SIZE+max", 239),
+            // Intermediate values may leave the byte range
+            ("max*max/max", 255),
+            ("1000-999", 1),
+            ("1<<62>>60", 4),
+            ("1>>64", 0),
+        ];
+
+        for (expression, expected) in cases {
+            match evaluate(expression, lookup) {
+                Ok(value) => assert_eq!(value, expected, "{}", expression),
+                Err(e) => panic!("{}: {}", expression, e),
+            }
+        }
+    }
+
+    #[test]
+    fn reports_invalid_expressions() {
+        let cases: [(&str, Expected); 14] = [
+            // Results are not wrapped to a byte
+            ("256", |e| matches!(e, ExpressionError::OutOfRange(256))),
+            ("-1", |e| matches!(e, ExpressionError::OutOfRange(-1))),
+            ("max+1", |e| matches!(e, ExpressionError::OutOfRange(256))),
+            // Overflow of the 64-bit intermediate values
+            ("0x7FFFFFFFFFFFFFFF+1", |e| {
+                matches!(e, ExpressionError::Overflow)
+            }),
+            ("1<<63", |e| matches!(e, ExpressionError::Overflow)),
+            ("1<<-1", |e| matches!(e, ExpressionError::Overflow)),
+            ("1/0", |e| matches!(e, ExpressionError::DivisionByZero)),
+            ("1%(SIZE-16)", |e| {
+                matches!(e, ExpressionError::DivisionByZero)
+            }),
+            (
+                "unknown+1",
+                |e| matches!(e, ExpressionError::UnknownName(name) if name == "unknown"),
+            ),
+            ("(1+2", |e| {
+                matches!(e, ExpressionError::InvalidExpression(..))
+            }),
+            ("1+", |e| {
+                matches!(e, ExpressionError::InvalidExpression(..))
+            }),
+            ("1 2", |e| {
+                matches!(e, ExpressionError::InvalidExpression(..))
+            }),
+            ("0xZZ", |e| {
+                matches!(e, ExpressionError::InvalidExpression(..))
+            }),
+            ("'AB'", |e| {
+                matches!(e, ExpressionError::InvalidExpression(..))
+            }),
+        ];
+
+        for (expression, expected) in cases {
+            match evaluate(expression, lookup) {
+                Ok(value) => panic!("{}: expected an error, got {}", expression, value),
+                Err(e) => assert!(expected(&e), "{}: unexpected error {:?}", expression, e),
+            }
+        }
+    }
+
+    #[test]
+    fn replaces_names_only() {
+        let replaced = replace_names("SIZE+0x1F-'S'+max", |name| {
+            lookup(name).map(|v| v.to_string())
+        });
+        assert_eq!(replaced, "16+0x1F-'S'+255");
+    }
+}
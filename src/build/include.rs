@@ -0,0 +1,118 @@
+use std::path::{Path, PathBuf};
+
+use super::{AssemblerError, AssemblerResult, ProgramAssembler};
+use crate::error::Diagnostics;
+
+impl ProgramAssembler {
+    /// Finds an included file relative to the including file, then in the search path
+    fn resolve_include(&self, path: &str) -> Option<PathBuf> {
+        // Without a file, e.g. when assembling from a reader, relative to the current directory
+        let base = self
+            .files
+            .last()
+            .and_then(|file| file.parent())
+            .unwrap_or(Path::new(""));
+
+        std::iter::once(base)
+            .chain(self.search_path.iter().map(PathBuf::as_path))
+            .map(|dir| dir.join(path))
+            .find(|candidate| candidate.is_file())
+    }
+
+    /// Assembles an included file in place of the `.include` directive.
+    /// Files that have already been included are skipped.
+    pub(super) fn include(
+        &mut self,
+        path: String,
+        diagnostics: &mut Diagnostics,
+    ) -> AssemblerResult<()> {
+        let Some(resolved) = self.resolve_include(&path) else {
+            return Err(AssemblerError::IncludeNotFound(path));
+        };
+
+        let canonical = match resolved.canonicalize() {
+            Ok(v) => v,
+            Err(e) => return Err(AssemblerError::IncludeRead(path, e)),
+        };
+
+        let including = |file: &PathBuf| file.canonicalize().is_ok_and(|file| file == canonical);
+        if self.files.iter().any(including) {
+            return Err(AssemblerError::CyclicInclude(path));
+        }
+
+        if !self.included.insert(canonical.clone()) {
+            return Ok(());
+        }
+
+        let source = match std::fs::read(&resolved) {
+            Ok(v) => v,
+            Err(e) => return Err(AssemblerError::IncludeRead(path, e)),
+        };
+
+        let id = self.sources.add(
+            resolved.clone(),
+            String::from_utf8_lossy(&source).into_owned(),
+        );
+        // Local labels of the included file belong to its own global labels, and its anonymous
+        // labels are numbered apart from those of the including file, like in macro expansions.
+        // Source ids cannot be mistaken for macro names, which do not start with a digit.
+        let outer = (
+            self.scope.take(),
+            std::mem::take(&mut self.backward_labels),
+            std::mem::take(&mut self.forward_labels),
+            std::mem::replace(&mut self.anonymous_prefix, format!("{}@.", id.0)),
+        );
+        self.files.push(resolved);
+        self.consume_source(source.as_slice(), id, diagnostics);
+        self.files.pop();
+        (
+            self.scope,
+            self.backward_labels,
+            self.forward_labels,
+            self.anonymous_prefix,
+        ) = outer;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        build::{Program, ProgramAssembler},
+        instruction::Instruction,
+        Sources,
+    };
+
+    #[test]
+    fn numbers_anonymous_labels_per_file() {
+        let dir = std::env::temp_dir().join(format!("rings-include-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("lib.inc"), ":-\n    jmp :-\n    jmp :+\n:+\n").unwrap();
+        let path = dir.join("main.rn");
+        let source = "\
+:-
+    mkr 1
+    jeq 0 0 :+
+.include \"lib.inc\"
+    jmp :-
+:+
+    hlt 0";
+        std::fs::write(&path, source).unwrap();
+
+        let program = ProgramAssembler::assemble_file(
+            &path,
+            source.as_bytes(),
+            &[],
+            false,
+            &mut Sources::new(),
+        );
+        std::fs::remove_dir_all(dir).unwrap();
+
+        let Program::Unlocalized(instructions) = program.unwrap() else {
+            unreachable!()
+        };
+        let targets: Vec<_> = instructions.iter().map(Instruction::target).collect();
+        assert_eq!(targets, [None, Some(5), Some(2), Some(4), Some(0), None]);
+    }
+}
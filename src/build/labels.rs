@@ -0,0 +1,166 @@
+use super::{
+    statement::{InstructionArg, InstructionStatement},
+    AssemblerError, AssemblerResult, ProgramAssembler,
+};
+use crate::Localized;
+
+/// Whether `label` is an anonymous label, `-` jumping backward or `+` jumping forward
+pub(super) fn is_anonymous(label: &str) -> bool {
+    matches!(label, "-" | "+")
+}
+
+/// The label as written in the source, for the unique names given to anonymous labels
+/// and to the labels of macro expansions
+pub(super) fn source_name(label: &str) -> &str {
+    match label.split_once('@') {
+        Some((anonymous, _)) if is_anonymous(anonymous) => anonymous,
+        Some((_, expansion)) => expansion
+            .split_once('.')
+            .map_or(label, |(_, label)| source_name(label)),
+        None => label,
+    }
+}
+
+impl ProgramAssembler {
+    /// Full name of a local label such as `.loop`, prefixed with the global label it belongs to
+    fn local(&self, label: String) -> String {
+        match &self.scope {
+            Some(scope) => format!("{}{}", scope, label),
+            None => label,
+        }
+    }
+
+    /// Binds a label to the next instruction. A global label starts a new scope for local labels,
+    /// except in macro expansions, whose labels are renamed for each expansion.
+    pub(super) fn define_label(
+        &mut self,
+        label: String,
+        location: &Localized<()>,
+    ) -> AssemblerResult<()> {
+        let label = match label.as_str() {
+            "-" => {
+                self.backward_labels += 1;
+                format!("{}-@{}", self.anonymous_prefix, self.backward_labels)
+            }
+            "+" => {
+                self.forward_labels += 1;
+                format!("{}+@{}", self.anonymous_prefix, self.forward_labels)
+            }
+            _ if label.starts_with('.') => self.local(label),
+            _ => {
+                if self.expansion.is_empty() {
+                    self.scope = Some(label.clone());
+                }
+                label
+            }
+        };
+
+        #[allow(clippy::map_entry)]
+        if self.labels.contains_key(&label) {
+            Err(AssemblerError::DuplicateLabel(label))
+        } else {
+            self.label_locations.insert(label.clone(), location.clone());
+            self.labels.insert(label, self.instructions.len());
+            Ok(())
+        }
+    }
+
+    /// Replaces local and anonymous labels jumped to by `instruction` with their full names.
+    /// `:-` refers to the closest `:-` before the jump, `:+` to the closest `:+` after it.
+    pub(super) fn resolve_labels(&self, instruction: InstructionStatement) -> InstructionStatement {
+        instruction.map_args(|arg| match arg {
+            InstructionArg::Label(label) => InstructionArg::Label(match label.as_str() {
+                "-" => format!("{}-@{}", self.anonymous_prefix, self.backward_labels),
+                "+" => format!("{}+@{}", self.anonymous_prefix, self.forward_labels + 1),
+                _ if label.starts_with('.') => self.local(label),
+                _ => label,
+            }),
+            arg => arg,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        build::{Program, ProgramAssembler},
+        instruction::Instruction,
+    };
+
+    /// Jump target of each instruction of `source`
+    fn targets(source: &str) -> Vec<Option<usize>> {
+        let program = ProgramAssembler::assemble(source.as_bytes(), false).unwrap();
+        let Program::Unlocalized(instructions) = program else {
+            unreachable!()
+        };
+
+        instructions.iter().map(Instruction::target).collect()
+    }
+
+    #[test]
+    fn resolves_local_labels_in_their_scope() {
+        let source = "\
+:first
+    mkr 1
+:.loop
+    jmp :.loop
+:second
+:.loop
+    jmp :.loop
+    jmp :first.loop";
+        assert_eq!(targets(source), [None, Some(1), Some(2), Some(1)]);
+    }
+
+    #[test]
+    fn resolves_anonymous_labels_in_order() {
+        let source = "\
+    mkr 1
+:-
+    jmp :+
+    jmp :-
+:+
+:-
+    jmp :-
+    jmp :+
+:+";
+        assert_eq!(targets(source), [None, Some(3), Some(1), Some(3), Some(5)]);
+    }
+
+    #[test]
+    fn keeps_anonymous_labels_inside_macro_expansions() {
+        let source = "\
+.macro spin r
+:-
+    rot r 1
+    jgt r r :-
+.endm
+.macro skip r
+    jeq r r :+
+    out r
+:+
+.endm
+    mkr 1
+:-
+    out 0
+    jmp :+
+    spin 0
+    skip 0
+    jmp :-
+:+
+    hlt 0";
+        assert_eq!(
+            targets(source),
+            [
+                None,
+                None,
+                Some(8),
+                None,
+                Some(3),
+                Some(7),
+                None,
+                Some(1),
+                None
+            ]
+        );
+    }
+}
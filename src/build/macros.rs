@@ -0,0 +1,293 @@
+use std::collections::{HashMap, HashSet};
+
+use super::{
+    expression::replace_names,
+    labels::is_anonymous,
+    statement::{InstructionArg, Statement},
+    AssemblerError, AssemblerResult, ProgramAssembler,
+};
+use crate::{error::Diagnostics, instruction::InstructionPrimitive, Localized};
+
+/// A macro defined with `.macro`
+pub(super) struct Macro {
+    params: Vec<String>,
+    body: Vec<Localized<Statement>>,
+    /// Location of the `.macro` directive
+    location: Localized<()>,
+}
+
+/// Substitutes the arguments of one macro call into the statements of the macro body
+struct Instance<'a> {
+    bindings: HashMap<&'a str, &'a InstructionArg>,
+    /// Labels defined in the macro body, which are renamed for each expansion
+    labels: HashSet<&'a str>,
+    prefix: String,
+}
+
+/// Whether `name` is an instruction mnemonic, which is never parsed as a macro call
+fn is_mnemonic(name: &str) -> bool {
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), chars.next(), chars.next()) {
+        (Some(a), Some(b), Some(c), None) => InstructionPrimitive::try_from((a, b, c)).is_ok(),
+        _ => false,
+    }
+}
+
+impl Macro {
+    pub(super) fn arity(&self) -> usize {
+        self.params.len()
+    }
+}
+
+impl Instance<'_> {
+    fn label(&self, label: String) -> String {
+        if self.labels.contains(label.as_str()) {
+            format!("{}.{}", self.prefix, label)
+        } else {
+            label
+        }
+    }
+
+    fn arg(&self, arg: InstructionArg) -> InstructionArg {
+        match arg {
+            InstructionArg::Expression(name) | InstructionArg::Label(name)
+                if self.bindings.contains_key(name.as_str()) =>
+            {
+                self.bindings[name.as_str()].clone()
+            }
+            InstructionArg::Label(label) => InstructionArg::Label(self.label(label)),
+            InstructionArg::Expression(expression) => {
+                InstructionArg::Expression(replace_names(&expression, |name| {
+                    match self.bindings.get(name)? {
+                        InstructionArg::Number(value, ..) => Some(value.to_string()),
+                        InstructionArg::Expression(expression) => Some(format!("({})", expression)),
+                        InstructionArg::Label(..) => None,
+                    }
+                }))
+            }
+            arg => arg,
+        }
+    }
+
+    fn statement(&self, statement: Statement) -> Statement {
+        match statement {
+            Statement::Label(label) => Statement::Label(self.label(label)),
+            Statement::Instruction(i) => Statement::Instruction(i.map_args(|arg| self.arg(arg))),
+            Statement::RingAlias { name, ring } => Statement::RingAlias {
+                name,
+                ring: self.arg(ring),
+            },
+            Statement::Constant { name, value } => Statement::Constant {
+                name,
+                value: self.arg(value),
+            },
+            Statement::Print { ring, text } => Statement::Print {
+                ring: self.arg(ring),
+                text,
+            },
+            Statement::Data { ring, text } => Statement::Data {
+                ring: self.arg(ring),
+                text,
+            },
+            Statement::MacroCall { name, args } => Statement::MacroCall {
+                name,
+                args: args.into_iter().map(|arg| self.arg(arg)).collect(),
+            },
+            statement @ (Statement::Comment(..)
+            | Statement::MacroStart { .. }
+            | Statement::MacroEnd
+            | Statement::Include(..)) => statement,
+        }
+    }
+}
+
+impl ProgramAssembler {
+    /// Starts the definition of a macro, the following statements up to `.endm` are its body
+    pub(super) fn start_macro(
+        &mut self,
+        name: String,
+        params: Vec<String>,
+        location: &Localized<()>,
+    ) -> AssemblerResult<()> {
+        let duplicate = self.macros.contains_key(&name);
+        let mnemonic = is_mnemonic(&name);
+        self.definition = Some((
+            name.clone(),
+            Macro {
+                params,
+                body: Vec::new(),
+                location: location.clone(),
+            },
+        ));
+
+        if mnemonic {
+            return Err(AssemblerError::MacroNameIsInstruction(name));
+        }
+        if duplicate {
+            return Err(AssemblerError::DuplicateMacro(name));
+        }
+        Ok(())
+    }
+
+    /// Adds a statement to the body of the macro being defined, or ends the definition
+    pub(super) fn define(
+        &mut self,
+        statement: Statement,
+        location: &Localized<()>,
+    ) -> AssemblerResult<()> {
+        let Some((name, definition)) = &mut self.definition else {
+            unreachable!()
+        };
+
+        match statement {
+            Statement::MacroStart { .. } => Err(AssemblerError::NestedMacro(name.clone())),
+            Statement::Include(..) => Err(AssemblerError::IncludeInMacro(name.clone())),
+            Statement::MacroEnd => {
+                let Some((name, definition)) = self.definition.take() else {
+                    unreachable!()
+                };
+
+                // The first definition of a duplicate macro is kept
+                if !is_mnemonic(&name) {
+                    self.macros.entry(name).or_insert(definition);
+                }
+                Ok(())
+            }
+            statement => {
+                definition.body.push(location.transform(statement));
+                Ok(())
+            }
+        }
+    }
+
+    /// Reports a macro definition missing its `.endm`
+    pub(super) fn finish_macros(&mut self, diagnostics: &mut Diagnostics) {
+        if let Some((name, definition)) = self.definition.take() {
+            let error = AssemblerError::UnterminatedMacro(name);
+            self.report(
+                diagnostics,
+                definition.location.transform(error.into()),
+                &[],
+            );
+        }
+    }
+
+    /// Expands a macro call. The statements of the expansion keep the locations in the macro body,
+    /// the call site is kept for error reporting.
+    pub(super) fn expand(
+        &mut self,
+        name: String,
+        args: Vec<InstructionArg>,
+        location: &Localized<()>,
+        diagnostics: &mut Diagnostics,
+    ) -> AssemblerResult<()> {
+        if self.expansion.iter().any(|call| call.value == name) {
+            return Err(AssemblerError::RecursiveMacro(name));
+        }
+
+        let Some(definition) = self.macros.get(&name) else {
+            return Err(AssemblerError::MacroNotFound(name));
+        };
+
+        self.expansions += 1;
+        let instance = Instance {
+            bindings: definition
+                .params
+                .iter()
+                .map(String::as_str)
+                .zip(&args)
+                .collect(),
+            labels: definition
+                .body
+                .iter()
+                .filter_map(|statement| match &statement.value {
+                    // Anonymous labels are numbered per expansion when they are defined
+                    Statement::Label(label) if is_anonymous(label) => None,
+                    Statement::Label(label) => Some(label.as_str()),
+                    _ => None,
+                })
+                .collect(),
+            prefix: format!("{}@{}", name, self.expansions),
+        };
+
+        let body: Vec<_> = definition
+            .body
+            .iter()
+            .map(|statement| statement.clone().map(|s| instance.statement(s)))
+            .collect();
+
+        // Anonymous labels of the body are numbered apart from those around the call, so that
+        // `:-` and `:+` never cross the boundaries of the expansion
+        let anonymous = format!("{}.", instance.prefix);
+        let outer = (
+            std::mem::take(&mut self.backward_labels),
+            std::mem::take(&mut self.forward_labels),
+            std::mem::replace(&mut self.anonymous_prefix, anonymous),
+        );
+
+        self.expansion.push(location.transform(name));
+        for statement in body {
+            self.consume_statement(statement, diagnostics);
+        }
+        self.expansion.pop();
+        (
+            self.backward_labels,
+            self.forward_labels,
+            self.anonymous_prefix,
+        ) = outer;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build::ProgramAssembler;
+
+    /// Line and message of each error reported for `source`
+    fn errors(source: &str) -> Vec<(usize, String)> {
+        let Err(diagnostics) = ProgramAssembler::assemble(source.as_bytes(), true) else {
+            panic!("expected errors");
+        };
+
+        diagnostics
+            .iter()
+            .map(|d| (d.location().unwrap().1, (*d.error).to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn reports_wrong_number_of_arguments() {
+        let source = "\
+.macro inc r n
+    put r n
+.endm
+mkr 1
+inc 0
+inc 0 1 2 3
+inc 0 1 inc 0 1
+inc";
+        assert_eq!(
+            errors(source),
+            [
+                (5, "Macro inc takes 2 arguments, got 1".to_owned()),
+                (6, "Macro inc takes 2 arguments, got 4".to_owned()),
+                (8, "Macro inc takes 2 arguments, got 0".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_instruction_names() {
+        let source = "\
+.macro add r
+    put r 1
+.endm
+mkr 1
+add 0 0 0";
+        assert_eq!(
+            errors(source),
+            [(1, "Macro name add is an instruction mnemonic".to_owned())]
+        );
+    }
+}
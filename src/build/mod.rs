@@ -1,16 +1,30 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
 
 use char::CharIterator;
-use statement::{InstructionArg, InstructionStatement, Statement, StatementParser};
-use token::Tokenizer;
+use expression::ExpressionError;
+use statement::{
+    InstructionArg, InstructionStatement, Statement, StatementParser, StatementParserError,
+};
+use token::{Token, Tokenizer, TokenizerError};
 
 use crate::{
-    error::{MaybeLocalizedRingsResult, RingsError},
-    instruction::{Instruction, InstructionError, InstructionPrimitive},
-    Localized, MaybeLocalized,
+    error::{Diagnostic, Diagnostics, DiagnosticsResult, RingsError},
+    instruction::{Instruction, InstructionError, InstructionPrimitive, Label},
+    lint::{self, DefinedLabel, Warning},
+    Localized, MaybeLocalized, NumberSystem, SourceId, Sources,
 };
 
+pub mod builder;
+pub mod bytecode;
 pub mod char;
+mod disasm;
+pub mod expression;
+mod include;
+mod labels;
+mod macros;
 pub mod statement;
 pub mod token;
 
@@ -18,8 +32,35 @@ type AssemblerResult<T> = Result<T, AssemblerError>;
 #[derive(Debug)]
 pub enum AssemblerError {
     DuplicateLabel(String),
+    DuplicateRingAlias(String),
+    RingAliasNotFound(String),
+    DuplicateConstant(String),
+    /// A constant expression could not be evaluated
+    Expression(ExpressionError),
+    DuplicateMacro(String),
+    MacroNotFound(String),
+    /// A macro named like an instruction, so it could never be called
+    MacroNameIsInstruction(String),
+    /// `.macro` inside the definition of the given macro
+    NestedMacro(String),
+    /// `.endm` without `.macro`
+    UnmatchedMacroEnd,
+    /// A macro definition without `.endm`
+    UnterminatedMacro(String),
+    /// A macro expanding to a call of itself
+    RecursiveMacro(String),
+    IncludeNotFound(String),
+    IncludeRead(String, std::io::Error),
+    /// A file including itself, directly or through other files
+    CyclicInclude(String),
+    /// `.include` inside the definition of the given macro
+    IncludeInMacro(String),
     InvalidInstructionArguments(InstructionPrimitive),
     LabelNotFound(String),
+    /// A [`builder::ProgramBuilder`] label that is jumped to, but never bound
+    UnboundLabel(String),
+    /// Jump past the end of the program
+    JumpOutOfRange(Label),
     WrongNumberOfArguments {
         primitive: InstructionPrimitive,
         expected: u8,
@@ -36,14 +77,43 @@ impl From<InstructionError> for AssemblerError {
     }
 }
 
+impl From<ExpressionError> for AssemblerError {
+    fn from(value: ExpressionError) -> Self {
+        Self::Expression(value)
+    }
+}
+
 impl std::fmt::Display for AssemblerError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Self::DuplicateLabel(lbl) => write!(f, "Duplicate label: {}", lbl),
+            Self::DuplicateRingAlias(name) => write!(f, "Duplicate ring alias: {}", name),
+            Self::RingAliasNotFound(name) => write!(f, "Ring alias not found: {}", name),
+            Self::DuplicateConstant(name) => write!(f, "Duplicate constant: {}", name),
+            Self::Expression(e) => write!(f, "{}", e),
+            Self::DuplicateMacro(name) => write!(f, "Duplicate macro: {}", name),
+            Self::MacroNotFound(name) => write!(f, "Macro not found: {}", name),
+            Self::MacroNameIsInstruction(name) => {
+                write!(f, "Macro name {} is an instruction mnemonic", name)
+            }
+            Self::NestedMacro(name) => {
+                write!(f, "Macro defined inside the definition of macro {}", name)
+            }
+            Self::UnmatchedMacroEnd => write!(f, ".endm without .macro"),
+            Self::UnterminatedMacro(name) => write!(f, "Macro {} is missing .endm", name),
+            Self::RecursiveMacro(name) => write!(f, "Recursive expansion of macro {}", name),
+            Self::IncludeNotFound(path) => write!(f, "Included file not found: {}", path),
+            Self::IncludeRead(path, e) => write!(f, "Cannot read included file {}: {}", path, e),
+            Self::CyclicInclude(path) => write!(f, "Cyclic include of {}", path),
+            Self::IncludeInMacro(name) => {
+                write!(f, "Include inside the definition of macro {}", name)
+            }
             Self::InvalidInstructionArguments(prim) => {
                 write!(f, "Invalid instruction arguments for {:?}", prim)
             }
             Self::LabelNotFound(lbl) => write!(f, "Label not found: {}", lbl),
+            Self::UnboundLabel(lbl) => write!(f, "Label never bound: {}", lbl),
+            Self::JumpOutOfRange(tgt) => write!(f, "Jump target {} out of range", tgt),
             Self::WrongNumberOfArguments {
                 primitive,
                 expected,
@@ -58,15 +128,31 @@ impl std::fmt::Display for AssemblerError {
     }
 }
 
+/// Call sites of the macros an instruction was expanded from, outermost first
+pub type CallSites = Vec<Localized<String>>;
+
+#[derive(Clone)]
 pub enum Program {
-    Localized(Vec<Localized<Instruction>>),
+    /// Instructions with their source locations, label names, and the macro call sites of the
+    /// instructions expanded from macros, by address
+    Localized(
+        Vec<Localized<Instruction>>,
+        HashMap<String, Label>,
+        HashMap<Label, CallSites>,
+    ),
     Unlocalized(Vec<Instruction>),
 }
 
+impl Default for Program {
+    fn default() -> Self {
+        Self::Unlocalized(Vec::new())
+    }
+}
+
 impl Program {
     fn new(preserve_location: bool) -> Self {
         if preserve_location {
-            Self::Localized(Vec::new())
+            Self::Localized(Vec::new(), HashMap::new(), HashMap::new())
         } else {
             Self::Unlocalized(Vec::new())
         }
@@ -75,53 +161,319 @@ impl Program {
     // Intentionally not pub
     fn push(&mut self, instr: Localized<Instruction>) {
         match self {
-            Self::Localized(vec) => vec.push(instr),
+            Self::Localized(vec, ..) => vec.push(instr),
             Self::Unlocalized(vec) => vec.push(instr.value),
         }
     }
 
     pub fn get(&self, index: usize) -> Option<MaybeLocalized<Instruction>> {
         match self {
-            Self::Localized(l) => l.get(index).map(|v| MaybeLocalized::Localized(v.clone())),
+            Self::Localized(l, ..) => l.get(index).map(|v| MaybeLocalized::Localized(v.clone())),
             Self::Unlocalized(v) => v.get(index).map(|v| MaybeLocalized::General(*v)),
         }
     }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Localized(l, ..) => l.len(),
+            Self::Unlocalized(v) => v.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Label names, only available in localized programs
+    pub fn labels(&self) -> Option<&HashMap<String, Label>> {
+        match self {
+            Self::Localized(_, labels, _) => Some(labels),
+            Self::Unlocalized(..) => None,
+        }
+    }
+
+    /// Call sites of the macros the instruction at `address` was expanded from, outermost first.
+    /// Empty for instructions written outside macros and in programs without locations.
+    pub fn call_sites(&self, address: Label) -> &[Localized<String>] {
+        match self {
+            Self::Localized(_, _, call_sites) => {
+                call_sites.get(&address).map_or(&[], Vec::as_slice)
+            }
+            Self::Unlocalized(..) => &[],
+        }
+    }
 }
 
 pub struct ProgramAssembler {
     labels: HashMap<String, usize>,
+    /// Where each label is defined
+    label_locations: HashMap<String, Localized<()>>,
+    /// Ring aliases declared with `.ring`
+    rings: HashMap<String, u8>,
+    /// Constants declared with `.equ`
+    constants: HashMap<String, u8>,
     instructions: Vec<Localized<InstructionStatement>>,
+    macros: HashMap<String, macros::Macro>,
+    /// The macro being defined, between `.macro` and `.endm`
+    definition: Option<(String, macros::Macro)>,
+    /// Call sites of the macros being expanded, outermost first
+    expansion: Vec<Localized<String>>,
+    /// Number of macro expansions so far, to give the labels of each expansion unique names
+    expansions: usize,
+    /// Call sites of the macros that instructions were expanded from, by instruction index
+    call_sites: HashMap<usize, CallSites>,
+    sources: Sources,
+    /// Directories to look for included files in
+    search_path: Vec<PathBuf>,
+    /// Paths of the files being assembled, the main file first and the innermost included file last
+    files: Vec<PathBuf>,
+    /// All files included so far
+    included: HashSet<PathBuf>,
+    /// The last global label, which local labels such as `.loop` belong to
+    scope: Option<String>,
+    /// Number of anonymous labels `:-` defined so far
+    backward_labels: usize,
+    /// Number of anonymous labels `:+` defined so far
+    forward_labels: usize,
+    /// Prefix of the anonymous labels of the macro expansion or included file being assembled,
+    /// empty in the main file
+    anonymous_prefix: String,
+}
+
+/// Levenshtein distance between two strings
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != *cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+
+    row[b.len()]
 }
 
 impl ProgramAssembler {
+    /// The known name closest to `name`, if it is similar enough
+    fn suggest<'a, K>(name: &str, known: K) -> Option<&'a str>
+    where
+        K: Iterator<Item = &'a String>,
+    {
+        let max_distance = (name.chars().count() / 3).max(1);
+        known
+            .map(|known| (edit_distance(name, known), known))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .min()
+            .map(|(_, known)| known.as_str())
+    }
+
+    fn hint(&self, error: &RingsError) -> Option<String> {
+        match error {
+            RingsError::Assembler(AssemblerError::LabelNotFound(label)) if label == "-" => {
+                Some("there is no :- label before this jump".to_owned())
+            }
+            RingsError::Assembler(AssemblerError::LabelNotFound(label)) if label == "+" => {
+                Some("there is no :+ label after this jump".to_owned())
+            }
+            RingsError::Assembler(AssemblerError::LabelNotFound(label)) => {
+                Self::suggest(label, self.labels.keys())
+                    .map(|known| format!("did you mean :{}?", known))
+            }
+            RingsError::Assembler(AssemblerError::RingAliasNotFound(name)) => {
+                Self::suggest(name, self.rings.keys())
+                    .map(|known| format!("did you mean {}?", known))
+            }
+            RingsError::Assembler(AssemblerError::Expression(ExpressionError::UnknownName(
+                name,
+            ))) if self.rings.contains_key(name) => Some(format!(
+                "{} is a ring alias, only constants declared with .equ can be used here",
+                name
+            )),
+            RingsError::Assembler(AssemblerError::Expression(ExpressionError::UnknownName(
+                name,
+            ))) => Self::suggest(name, self.constants.keys().chain(self.rings.keys()))
+                .map(|known| format!("did you mean {}?", known)),
+            RingsError::StatementParser(StatementParserError::UnexpectedToken(Token::Word(
+                name,
+            )))
+            | RingsError::Assembler(AssemblerError::MacroNotFound(name)) => {
+                Self::suggest(name, self.macros.keys())
+                    .map(|known| format!("did you mean {}?", known))
+            }
+            RingsError::Tokenizer(TokenizerError::InvalidDigit(NumberSystem::Octal, _)) => Some(
+                "numbers with a leading 0 are octal, use 0x for hexadecimal or drop the 0"
+                    .to_owned(),
+            ),
+            _ => None,
+        }
+    }
+
+    /// Value of a number or constant expression
+    fn literal(&self, arg: InstructionArg) -> AssemblerResult<u8> {
+        match arg {
+            InstructionArg::Number(value, ..) => Ok(value),
+            InstructionArg::Expression(expression) => {
+                Ok(expression::evaluate(&expression, |name| {
+                    self.constants.get(name).copied()
+                })?)
+            }
+            InstructionArg::Label(..) => unreachable!(),
+        }
+    }
+
+    /// Ring id of a number, ring alias or constant expression, which may also refer to aliases
+    fn ring(&self, arg: InstructionArg) -> AssemblerResult<u8> {
+        let InstructionArg::Expression(expression) = arg else {
+            return self.literal(arg);
+        };
+
+        if let Some(ring) = self.rings.get(&expression) {
+            return Ok(*ring);
+        }
+
+        if expression::is_name(&expression) && !self.constants.contains_key(&expression) {
+            return Err(AssemblerError::RingAliasNotFound(expression));
+        }
+
+        Ok(expression::evaluate(&expression, |name| {
+            self.rings
+                .get(name)
+                .or_else(|| self.constants.get(name))
+                .copied()
+        })?)
+    }
+
+    /// Reports an error, with notes pointing at the macro calls it was expanded from
+    fn report(
+        &self,
+        diagnostics: &mut Diagnostics,
+        error: Localized<RingsError>,
+        call_sites: &[Localized<String>],
+    ) {
+        let hint = self.hint(&error);
+        diagnostics.push_diagnostic(Diagnostic::new(error, hint).with_call_sites(call_sites));
+    }
+
+    fn push_instruction(&mut self, instruction: Localized<InstructionStatement>) {
+        if !self.expansion.is_empty() {
+            self.call_sites
+                .insert(self.instructions.len(), self.expansion.clone());
+        }
+
+        self.instructions.push(instruction);
+    }
+
+    /// Handles a parsed statement, reporting its errors
+    fn consume_statement(
+        &mut self,
+        statement: Localized<Statement>,
+        diagnostics: &mut Diagnostics,
+    ) {
+        let (location, statement) = statement.cut();
+        let result = match statement {
+            statement if self.definition.is_some() => self.define(statement, &location),
+            Statement::MacroStart { name, params } => self.start_macro(name, params, &location),
+            Statement::MacroEnd => Err(AssemblerError::UnmatchedMacroEnd),
+            Statement::MacroCall { name, args } => self.expand(name, args, &location, diagnostics),
+            Statement::Include(path) => self.include(path, diagnostics),
+            statement => self.consume_raw_statement(statement, &location),
+        };
+
+        if let Err(e) = result {
+            self.report(diagnostics, location.transform(e.into()), &self.expansion);
+        }
+    }
+
     fn consume_raw_statement(
         &mut self,
         statement: Statement,
         location: &Localized<()>,
     ) -> AssemblerResult<()> {
         match statement {
-            Statement::Label(lbl) => {
-                #[allow(clippy::map_entry)]
-                if self.labels.contains_key(&lbl) {
-                    Err(AssemblerError::DuplicateLabel(lbl))
-                } else {
-                    self.labels.insert(lbl, self.instructions.len());
-                    Ok(())
+            Statement::Label(lbl) => self.define_label(lbl, location),
+            Statement::Instruction(i) => {
+                self.push_instruction(location.transform(self.resolve_labels(i)));
+                Ok(())
+            }
+            Statement::RingAlias { name, ring } => {
+                if self.rings.contains_key(&name) {
+                    return Err(AssemblerError::DuplicateRingAlias(name));
+                }
+
+                let ring = self.literal(ring)?;
+                self.rings.insert(name, ring);
+                Ok(())
+            }
+            Statement::Constant { name, value } => {
+                if self.constants.contains_key(&name) {
+                    return Err(AssemblerError::DuplicateConstant(name));
                 }
+
+                let value = self.literal(value)?;
+                self.constants.insert(name, value);
+                Ok(())
             }
-            Statement::Instruction(i) => {
-                self.instructions.push(location.transform(i));
+            Statement::Print { ring, text } => {
+                self.expand_text(location, ring, &text, |ring| {
+                    InstructionStatement::Instruction1(InstructionPrimitive::OUT, ring)
+                });
                 Ok(())
             }
+            Statement::Data { ring, text } => {
+                self.expand_text(location, ring, &text, |ring| {
+                    InstructionStatement::Instruction2(
+                        InstructionPrimitive::ROT,
+                        ring,
+                        InstructionArg::Number(1, NumberSystem::Decimal, 1),
+                    )
+                });
+                Ok(())
+            }
+            Statement::Comment(..) => Ok(()),
+            Statement::MacroStart { .. }
+            | Statement::MacroEnd
+            | Statement::MacroCall { .. }
+            | Statement::Include(..) => unreachable!(),
         }
     }
 
-    fn assemble_inner(self, preserve_location: bool) -> MaybeLocalized<AssemblerResult<Program>> {
-        let mut out = Program::new(preserve_location);
+    /// Puts each byte of `text` into `ring`, followed by the instruction made by `then`
+    fn expand_text<F>(
+        &mut self,
+        location: &Localized<()>,
+        ring: InstructionArg,
+        text: &[u8],
+        then: F,
+    ) where
+        F: Fn(InstructionArg) -> InstructionStatement,
+    {
+        for byte in text {
+            self.push_instruction(location.transform(InstructionStatement::Instruction2(
+                InstructionPrimitive::PUT,
+                ring.clone(),
+                InstructionArg::Number(*byte, NumberSystem::Character, 1),
+            )));
+            self.push_instruction(location.transform(then(ring.clone())));
+        }
+    }
 
+    fn assemble_instruction(
+        &self,
+        instruction_stmt: Localized<InstructionStatement>,
+    ) -> Result<Localized<Instruction>, Localized<AssemblerError>> {
         macro_rules! pattern_arg {
             (num $ident:ident) => {
-                InstructionArg::Number($ident)
+                $ident @ (InstructionArg::Number(..) | InstructionArg::Expression(..))
+            };
+
+            (ring $ident:ident) => {
+                $ident @ (InstructionArg::Number(..) | InstructionArg::Expression(..))
             };
 
             (lbl $ident:ident) => {
@@ -130,17 +482,26 @@ impl ProgramAssembler {
         }
 
         macro_rules! process_arg {
-            ($location:expr, num $ident:ident) => {
-                $ident
-            };
+            ($location:expr, num $ident:ident) => {{
+                match self.literal($ident) {
+                    Ok(v) => v,
+                    Err(e) => return Err($location.transform(e)),
+                }
+            }};
+
+            ($location:expr, ring $ident:ident) => {{
+                match self.ring($ident) {
+                    Ok(v) => v,
+                    Err(e) => return Err($location.transform(e)),
+                }
+            }};
 
             ($location:expr, lbl $ident:ident) => {{
                 match self.labels.get(&$ident) {
                     Some(v) => *v,
                     None => {
-                        return MaybeLocalized::Localized(
-                            $location.transform(Err(AssemblerError::LabelNotFound($ident.clone()))),
-                        )
+                        let label = labels::source_name(&$ident).to_owned();
+                        return Err($location.transform(AssemblerError::LabelNotFound(label)));
                     }
                 }
             }};
@@ -153,113 +514,269 @@ impl ProgramAssembler {
                         $location.transform(Instruction::$tgt($(process_arg!($location, $typ $arg)),+))
                     }
                     _ => {
-                        return MaybeLocalized::Localized($location.transform(Err(AssemblerError::InvalidInstructionArguments(
+                        return Err($location.transform(AssemblerError::InvalidInstructionArguments(
                             $primitive
-                        ))));
+                        )));
                     }
                 }
             };
         }
 
-        for instruction_stmt in self.instructions {
-            let (location, instruction_stmt) = instruction_stmt.cut();
-            let instr = match instruction_stmt {
-                InstructionStatement::Instruction1(prim, a) => match prim {
-                    InstructionPrimitive::MKR => build_instr!(location, prim, MKR; num a),
-                    InstructionPrimitive::INP => build_instr!(location, prim, INP; num a),
-                    InstructionPrimitive::OUT => build_instr!(location, prim, OUT; num a),
-                    InstructionPrimitive::ERR => build_instr!(location, prim, ERR; num a),
-                    InstructionPrimitive::JMP => build_instr!(location, prim, JMP; lbl a),
-                    InstructionPrimitive::HLT => build_instr!(location, prim, HLT; num a),
-                    primitive => {
-                        return MaybeLocalized::Localized(location.transform(Err(
-                            AssemblerError::WrongNumberOfArguments {
-                                expected: primitive.get_num_args(),
-                                primitive,
-                                got: 1,
-                            },
-                        )))
-                    }
-                },
-                InstructionStatement::Instruction2(prim, a, b) => match prim {
-                    InstructionPrimitive::PUT => build_instr!(location, prim, PUT; num a, num b),
-                    InstructionPrimitive::ROT => build_instr!(location, prim, ROT; num a, num b),
-                    InstructionPrimitive::SWP => build_instr!(location, prim, SWP; num a, num b),
-                    primitive => {
-                        return MaybeLocalized::Localized(location.transform(Err(
-                            AssemblerError::WrongNumberOfArguments {
-                                expected: primitive.get_num_args(),
-                                primitive,
-                                got: 2,
-                            },
-                        )))
-                    }
-                },
-                InstructionStatement::Instruction3(prim, a, b, c) => match prim {
-                    InstructionPrimitive::ADD => {
-                        build_instr!(location, prim, ADD; num a, num b, num c)
-                    }
-                    InstructionPrimitive::SUB => {
-                        build_instr!(location, prim, SUB; num a, num b, num c)
-                    }
-                    InstructionPrimitive::MUL => {
-                        build_instr!(location, prim, MUL; num a, num b, num c)
-                    }
-                    InstructionPrimitive::DIV => {
-                        build_instr!(location, prim, DIV; num a, num b, num c)
-                    }
-                    InstructionPrimitive::JEQ => {
-                        build_instr!(location, prim, JEQ; num a, num b, lbl c)
-                    }
-                    InstructionPrimitive::JGT => {
-                        build_instr!(location, prim, JGT; num a, num b, lbl c)
-                    }
-                    InstructionPrimitive::JLT => {
-                        build_instr!(location, prim, JLT; num a, num b, lbl c)
-                    }
-                    primitive => {
-                        return MaybeLocalized::Localized(location.transform(Err(
-                            AssemblerError::WrongNumberOfArguments {
-                                expected: primitive.get_num_args(),
-                                primitive,
-                                got: 3,
-                            },
-                        )))
-                    }
-                },
-            };
+        let (location, instruction_stmt) = instruction_stmt.cut();
+        let instr = match instruction_stmt {
+            InstructionStatement::Instruction1(prim, a) => match prim {
+                InstructionPrimitive::MKR => build_instr!(location, prim, MKR; num a),
+                InstructionPrimitive::INP => build_instr!(location, prim, INP; ring a),
+                InstructionPrimitive::OUT => build_instr!(location, prim, OUT; ring a),
+                InstructionPrimitive::ERR => build_instr!(location, prim, ERR; ring a),
+                InstructionPrimitive::JMP => build_instr!(location, prim, JMP; lbl a),
+                InstructionPrimitive::HLT => build_instr!(location, prim, HLT; num a),
+                primitive => {
+                    return Err(location.transform(AssemblerError::WrongNumberOfArguments {
+                        expected: primitive.get_num_args(),
+                        primitive,
+                        got: 1,
+                    }))
+                }
+            },
+            InstructionStatement::Instruction2(prim, a, b) => match prim {
+                InstructionPrimitive::PUT => build_instr!(location, prim, PUT; ring a, num b),
+                InstructionPrimitive::ROT => build_instr!(location, prim, ROT; ring a, num b),
+                InstructionPrimitive::SWP => build_instr!(location, prim, SWP; ring a, ring b),
+                InstructionPrimitive::INF => build_instr!(location, prim, INF; ring a, ring b),
+                primitive => {
+                    return Err(location.transform(AssemblerError::WrongNumberOfArguments {
+                        expected: primitive.get_num_args(),
+                        primitive,
+                        got: 2,
+                    }))
+                }
+            },
+            InstructionStatement::Instruction3(prim, a, b, c) => match prim {
+                InstructionPrimitive::ADD => {
+                    build_instr!(location, prim, ADD; ring a, ring b, ring c)
+                }
+                InstructionPrimitive::SUB => {
+                    build_instr!(location, prim, SUB; ring a, ring b, ring c)
+                }
+                InstructionPrimitive::MUL => {
+                    build_instr!(location, prim, MUL; ring a, ring b, ring c)
+                }
+                InstructionPrimitive::DIV => {
+                    build_instr!(location, prim, DIV; ring a, ring b, ring c)
+                }
+                InstructionPrimitive::JEQ => {
+                    build_instr!(location, prim, JEQ; ring a, ring b, lbl c)
+                }
+                InstructionPrimitive::JGT => {
+                    build_instr!(location, prim, JGT; ring a, ring b, lbl c)
+                }
+                InstructionPrimitive::JLT => {
+                    build_instr!(location, prim, JLT; ring a, ring b, lbl c)
+                }
+                primitive => {
+                    return Err(location.transform(AssemblerError::WrongNumberOfArguments {
+                        expected: primitive.get_num_args(),
+                        primitive,
+                        got: 3,
+                    }))
+                }
+            },
+        };
+
+        if let Err(e) = instr.validate() {
+            return Err(location.transform(e.into()));
+        }
 
-            if let Err(e) = instr.validate() {
-                return MaybeLocalized::Localized(location.transform(Err(e.into())));
+        Ok(instr)
+    }
+
+    fn assemble_inner(mut self, preserve_location: bool, diagnostics: &mut Diagnostics) -> Program {
+        let mut out = Program::new(preserve_location);
+
+        for (index, instruction_stmt) in std::mem::take(&mut self.instructions)
+            .into_iter()
+            .enumerate()
+        {
+            match self.assemble_instruction(instruction_stmt) {
+                Ok(instr) => out.push(instr),
+                Err(e) => {
+                    let call_sites = self.call_sites.get(&index).map_or(&[][..], Vec::as_slice);
+                    self.report(diagnostics, e.map(RingsError::from), call_sites)
+                }
             }
+        }
 
-            out.push(instr);
+        if let Program::Localized(_, labels, call_sites) = &mut out {
+            *labels = self.labels;
+            // Instructions are numbered as in the program, no instruction has been left out
+            // unless there are errors
+            *call_sites = self.call_sites;
         }
 
-        MaybeLocalized::General(Ok(out))
+        out
+    }
+
+    fn new(search_path: &[PathBuf]) -> Self {
+        Self {
+            labels: HashMap::with_capacity(50),
+            label_locations: HashMap::new(),
+            rings: HashMap::new(),
+            constants: HashMap::new(),
+            instructions: Vec::new(),
+            macros: HashMap::new(),
+            definition: None,
+            expansion: Vec::new(),
+            expansions: 0,
+            call_sites: HashMap::new(),
+            sources: Sources::new(),
+            search_path: search_path.to_vec(),
+            files: Vec::new(),
+            included: HashSet::new(),
+            scope: None,
+            backward_labels: 0,
+            forward_labels: 0,
+            anonymous_prefix: String::new(),
+        }
     }
 
-    pub fn assemble<R>(reader: R, preserve_location: bool) -> MaybeLocalizedRingsResult<Program>
+    /// Consumes the statements of one source file
+    fn consume_source<R>(&mut self, reader: R, source: SourceId, diagnostics: &mut Diagnostics)
     where
         R: std::io::Read,
     {
-        let chars = CharIterator::new(reader);
-        let tokens = Tokenizer::new(chars);
-        let statements = StatementParser::new(tokens);
+        let chars = CharIterator::with_source(reader, source);
+        let mut statements = StatementParser::new(Tokenizer::new(chars));
 
-        let mut ctx = Self {
-            labels: HashMap::with_capacity(50),
-            instructions: Vec::new(),
-        };
+        let mut known_macros = 0;
+        loop {
+            // Macros defined by the including file or by included files
+            if self.macros.len() != known_macros {
+                known_macros = self.macros.len();
+                for (name, definition) in &self.macros {
+                    statements.define_macro(name.clone(), definition.arity());
+                }
+            }
+
+            let Some(statement) = statements.next() else {
+                break;
+            };
 
-        for statement in statements {
-            let (location, statement) = statement?.cut();
-            if let Err(e) = ctx.consume_raw_statement(statement, &location) {
-                return MaybeLocalized::Localized(location.transform(Err(e.into())));
+            match statement.transpose() {
+                Ok(statement) => self.consume_statement(statement, diagnostics),
+                Err(e) => self.report(diagnostics, e, &[]),
             }
         }
 
-        ctx.assemble_inner(preserve_location)
-            .map(|e| e.map_err(RingsError::from))
+        self.finish_macros(diagnostics);
+    }
+
+    /// Assembles the whole program, reporting every error found rather than just the first one.
+    /// Included files are looked up relative to the current directory.
+    pub fn assemble<R>(reader: R, preserve_location: bool) -> DiagnosticsResult<Program>
+    where
+        R: std::io::Read,
+    {
+        let mut ctx = Self::new(&[]);
+        let mut diagnostics = Diagnostics::new();
+
+        let source = ctx.sources.add(PathBuf::new(), String::new());
+        ctx.consume_source(reader, source, &mut diagnostics);
+
+        let program = ctx.assemble_inner(preserve_location, &mut diagnostics);
+        diagnostics.into_result(program)
+    }
+
+    /// Assembles the program in `source`, read from the file at `path`, and the files it includes.
+    /// Included files are looked up relative to the including file, then in the directories of
+    /// `search_path`. All source files are added to `sources`, for rendering diagnostics.
+    pub fn assemble_file(
+        path: &Path,
+        source: &[u8],
+        search_path: &[PathBuf],
+        preserve_location: bool,
+        sources: &mut Sources,
+    ) -> DiagnosticsResult<Program> {
+        let mut diagnostics = Diagnostics::new();
+        let ctx = Self::read_file(path, source, search_path, sources, &mut diagnostics);
+
+        let program = ctx.assemble_inner(preserve_location, &mut diagnostics);
+        diagnostics.into_result(program)
+    }
+
+    /// Assembles the program like [`Self::assemble_file`] and checks it for likely mistakes with
+    /// [`lint::check`]. Warnings allowed by an `# allow(...)` comment are left out.
+    pub fn check_file(
+        path: &Path,
+        source: &[u8],
+        search_path: &[PathBuf],
+        sources: &mut Sources,
+    ) -> DiagnosticsResult<Vec<Localized<Warning>>> {
+        let mut diagnostics = Diagnostics::new();
+        let mut ctx = Self::read_file(path, source, search_path, sources, &mut diagnostics);
+
+        let jumped_to: HashSet<&str> = ctx
+            .instructions
+            .iter()
+            .filter_map(|instruction| match &instruction.value {
+                InstructionStatement::Instruction1(_, InstructionArg::Label(label))
+                | InstructionStatement::Instruction3(_, _, _, InstructionArg::Label(label)) => {
+                    Some(label.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        let scopes: HashSet<&str> = ctx
+            .labels
+            .keys()
+            .filter_map(|label| Some(label.split_once('.')?.0))
+            .collect();
+
+        let labels: Vec<_> = std::mem::take(&mut ctx.label_locations)
+            .into_iter()
+            .map(|(label, location)| {
+                location.transform(DefinedLabel {
+                    name: labels::source_name(&label).to_owned(),
+                    address: ctx.labels[&label],
+                    used: jumped_to.contains(label.as_str()) || scopes.contains(label.as_str()),
+                })
+            })
+            .collect();
+
+        let program = ctx.assemble_inner(true, &mut diagnostics);
+        let Program::Localized(instructions, ..) = diagnostics.into_result(program)? else {
+            unreachable!()
+        };
+
+        // Labels and instructions of a macro are checked once for every expansion
+        let mut seen = HashSet::new();
+        let mut warnings = lint::check(&instructions, &labels);
+        warnings.retain(|warning| {
+            let key = (warning.source, warning.start(), warning.to_string());
+            !lint::allowed(sources, warning) && seen.insert(key)
+        });
+        Ok(warnings)
+    }
+
+    /// Consumes the statements of the file at `path` and the files it includes
+    fn read_file(
+        path: &Path,
+        source: &[u8],
+        search_path: &[PathBuf],
+        sources: &mut Sources,
+        diagnostics: &mut Diagnostics,
+    ) -> Self {
+        let mut ctx = Self::new(search_path);
+
+        std::mem::swap(&mut ctx.sources, sources);
+        let id = ctx.sources.add(
+            path.to_owned(),
+            String::from_utf8_lossy(source).into_owned(),
+        );
+        ctx.files.push(path.to_owned());
+        ctx.consume_source(source, id, diagnostics);
+        std::mem::swap(&mut ctx.sources, sources);
+
+        ctx
     }
 }
@@ -1,6 +1,10 @@
-use crate::{error::LocalizedRingsResult, instruction::InstructionPrimitive, Localized};
+use std::collections::HashMap;
 
-use super::token::Token;
+use crate::{
+    error::LocalizedRingsResult, instruction::InstructionPrimitive, Localized, NumberSystem,
+};
+
+use super::{expression::is_name, token::Token};
 
 type StatementParserResult<T> = Result<T, StatementParserError>;
 #[derive(Debug)]
@@ -11,6 +15,18 @@ pub enum StatementParserError {
     /// Instr arg (Token::Colon | Token::Number) was expected, instead got self.0
     InstrArgExpected(Token),
     UnclosedStatement,
+    UnknownDirective(String),
+    /// The directive's arguments do not match the expected syntax
+    MalformedDirective {
+        directive: String,
+        expected: &'static str,
+    },
+    /// A macro called with the wrong number of arguments
+    MacroArguments {
+        name: String,
+        expected: usize,
+        got: usize,
+    },
 }
 
 impl std::error::Error for StatementParserError {}
@@ -24,14 +40,52 @@ impl std::fmt::Display for StatementParserError {
                 write!(f, "Expected instruction argument, got {:?}", instead)
             }
             Self::UnclosedStatement => write!(f, "Unclosed statement"),
+            Self::UnknownDirective(directive) => write!(f, "Unknown directive: {}", directive),
+            Self::MalformedDirective {
+                directive,
+                expected,
+            } => write!(
+                f,
+                "Malformed {} directive, expected {}",
+                directive, expected
+            ),
+            Self::MacroArguments {
+                name,
+                expected: 1,
+                got,
+            } => write!(f, "Macro {} takes 1 argument, got {}", name, got),
+            Self::MacroArguments {
+                name,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Macro {} takes {} arguments, got {}",
+                name, expected, got
+            ),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InstructionArg {
-    Number(u8),
+    /// A number, the number system it was written in and the number of digits it was written
+    /// with, leading zeros included
+    Number(u8, NumberSystem, usize),
     Label(String),
+    /// A ring alias, a constant declared with `.equ` or a constant expression, e.g. `SIZE-1`
+    Expression(String),
+}
+
+impl InstructionArg {
+    /// A directive value, either a number or a constant expression
+    fn value(token: Token) -> Option<Self> {
+        match token {
+            Token::Number(n, sys, digits) => Some(Self::Number(n, sys, digits)),
+            Token::Word(w) => Some(Self::Expression(w)),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Default, Clone, Copy)]
@@ -49,7 +103,8 @@ impl InstructionArgBuilder {
                     *self = InstructionArgBuilder::Colon;
                     Ok(None)
                 }
-                Token::Number(n) => Ok(Some(InstructionArg::Number(n))),
+                Token::Number(n, sys, digits) => Ok(Some(InstructionArg::Number(n, sys, digits))),
+                Token::Word(w) => Ok(Some(InstructionArg::Expression(w))),
                 token => Err(StatementParserError::InstrArgExpected(token)),
             },
             Self::Colon => match token {
@@ -63,7 +118,7 @@ impl InstructionArgBuilder {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub enum InstructionStatement {
     Instruction1(InstructionPrimitive, InstructionArg),
     Instruction2(InstructionPrimitive, InstructionArg, InstructionArg),
@@ -75,10 +130,141 @@ pub enum InstructionStatement {
     ),
 }
 
-#[derive(Debug)]
+impl InstructionStatement {
+    /// Replaces each argument with the result of `f`
+    pub fn map_args<F>(self, mut f: F) -> Self
+    where
+        F: FnMut(InstructionArg) -> InstructionArg,
+    {
+        match self {
+            Self::Instruction1(p, a) => Self::Instruction1(p, f(a)),
+            Self::Instruction2(p, a, b) => Self::Instruction2(p, f(a), f(b)),
+            Self::Instruction3(p, a, b, c) => Self::Instruction3(p, f(a), f(b), f(c)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
 pub enum Statement {
     Label(String),
     Instruction(InstructionStatement),
+    /// Only produced from [`Token::Comment`]s
+    Comment(String),
+    /// `.ring name = N`
+    RingAlias {
+        name: String,
+        ring: InstructionArg,
+    },
+    /// `.equ NAME value`
+    Constant {
+        name: String,
+        value: InstructionArg,
+    },
+    /// `.print <ring> "text"`, writes the text to stdout through the ring
+    Print {
+        ring: InstructionArg,
+        text: Vec<u8>,
+    },
+    /// `.data <ring> "text"`, writes the text into consecutive cells of the ring
+    Data {
+        ring: InstructionArg,
+        text: Vec<u8>,
+    },
+    /// `.macro <name> <parameter>...`, the following statements up to `.endm` are the macro body
+    MacroStart {
+        name: String,
+        params: Vec<String>,
+    },
+    /// `.endm`
+    MacroEnd,
+    /// Use of a macro defined earlier with `.macro`
+    MacroCall {
+        name: String,
+        args: Vec<InstructionArg>,
+    },
+    /// `.include "path"`
+    Include(String),
+}
+
+/// Known directives, with the number of tokens following them and their syntax.
+/// Directives without a number of tokens take the rest of the line.
+const DIRECTIVES: &[(&str, Option<usize>, &str)] = &[
+    (".ring", Some(3), ".ring <name> = <ring>"),
+    (".equ", Some(2), ".equ <name> <value>"),
+    (".print", Some(2), ".print <ring> \"<text>\""),
+    (".data", Some(2), ".data <ring> \"<text>\""),
+    (".macro", None, ".macro <name> <parameter>..."),
+    (".endm", Some(0), ".endm"),
+    (".include", Some(1), ".include \"<path>\""),
+];
+
+fn directive_syntax(directive: &str) -> Option<(Option<usize>, &'static str)> {
+    DIRECTIVES
+        .iter()
+        .find(|(name, _, _)| *name == directive)
+        .map(|(_, arity, syntax)| (*arity, *syntax))
+}
+
+fn malformed_directive(directive: String) -> StatementParserError {
+    let (_, expected) = directive_syntax(&directive).unwrap_or_default();
+    StatementParserError::MalformedDirective {
+        directive,
+        expected,
+    }
+}
+
+fn parse_directive(directive: String, args: Vec<Token>) -> StatementParserResult<Statement> {
+    let mut args = args.into_iter();
+    match directive.as_str() {
+        ".ring" => match (args.next(), args.next(), args.next()) {
+            (Some(Token::Word(name)), Some(Token::Word(eq)), Some(ring)) if eq == "=" => {
+                match InstructionArg::value(ring) {
+                    Some(ring) => Ok(Statement::RingAlias { name, ring }),
+                    None => Err(malformed_directive(directive)),
+                }
+            }
+            _ => Err(malformed_directive(directive)),
+        },
+        ".equ" => match (args.next(), args.next().and_then(InstructionArg::value)) {
+            (Some(Token::Word(name)), Some(value)) => Ok(Statement::Constant { name, value }),
+            _ => Err(malformed_directive(directive)),
+        },
+        ".print" | ".data" => match (args.next().and_then(InstructionArg::value), args.next()) {
+            (Some(ring), Some(Token::String(text))) if directive == ".print" => {
+                Ok(Statement::Print { ring, text })
+            }
+            (Some(ring), Some(Token::String(text))) => Ok(Statement::Data { ring, text }),
+            _ => Err(malformed_directive(directive)),
+        },
+        ".macro" => {
+            let names: Option<Vec<String>> = args
+                .enumerate()
+                .map(|(i, arg)| match arg {
+                    Token::Word(name) if is_name(&name) => Some(name),
+                    // Rejected by the assembler, which still skips the body up to `.endm`
+                    Token::InstructionPrimitive(primitive) if i == 0 => Some(primitive.to_string()),
+                    _ => None,
+                })
+                .collect();
+
+            match names.as_deref() {
+                Some([name, params @ ..]) => Ok(Statement::MacroStart {
+                    name: name.clone(),
+                    params: params.to_vec(),
+                }),
+                _ => Err(malformed_directive(directive)),
+            }
+        }
+        ".endm" => Ok(Statement::MacroEnd),
+        ".include" => match args.next() {
+            // Literals are ASCII
+            Some(Token::String(path)) => Ok(Statement::Include(
+                String::from_utf8_lossy(&path).into_owned(),
+            )),
+            _ => Err(malformed_directive(directive)),
+        },
+        _ => Err(StatementParserError::UnknownDirective(directive)),
+    }
 }
 
 #[derive(Default)]
@@ -98,6 +284,15 @@ enum StatementParserState {
         InstructionArg,
         InstructionArgBuilder,
     ),
+    /// A directive has been detected, collecting its arguments
+    Directive(String, Vec<Token>),
+    /// A macro call has been detected: name, number of parameters and the arguments so far
+    MacroCall(String, usize, Vec<InstructionArg>, InstructionArgBuilder),
+    /// Arguments past the parameters of a macro call: name, number of parameters and the
+    /// number of arguments so far
+    ExtraMacroArgs(String, usize, usize),
+    /// An error has been reported, skipping tokens until the end of the line
+    Recover,
 }
 
 pub struct StatementParser<I> {
@@ -105,6 +300,13 @@ pub struct StatementParser<I> {
     done: bool,
     state: StatementParserState,
     last_location: Localized<()>,
+    /// Macros defined so far and their number of parameters
+    macros: HashMap<String, usize>,
+    /// The macro call that ended with the previous token, more arguments are reported as extra
+    last_call: Option<(String, usize)>,
+    /// A comment ending a directive, to be emitted after it
+    comment: Option<String>,
+    carry: Option<LocalizedRingsResult<Statement>>,
 }
 
 impl<I> StatementParser<I>
@@ -117,13 +319,31 @@ where
             done: false,
             state: StatementParserState::default(),
             last_location: Localized::default(),
+            macros: HashMap::new(),
+            last_call: None,
+            comment: None,
+            carry: None,
         }
     }
 
+    /// Makes calls of a macro defined elsewhere, e.g. in an included file, parse as macro calls
+    pub fn define_macro(&mut self, name: String, arity: usize) {
+        self.macros.entry(name).or_insert(arity);
+    }
+
     fn consume(&mut self, token: Token) -> StatementParserResult<Option<Statement>> {
+        let last_call = self.last_call.take();
         match &mut self.state {
+            StatementParserState::Recover => {
+                if let Token::Newline = token {
+                    self.state = StatementParserState::Init;
+                }
+
+                Ok(None)
+            }
             StatementParserState::Init => match token {
                 Token::Newline => Ok(None),
+                Token::Comment(text) => Ok(Some(Statement::Comment(text))),
                 Token::Colon => {
                     self.state = StatementParserState::LabelStart;
                     Ok(None)
@@ -133,8 +353,111 @@ where
                         StatementParserState::InstrStart(instr, InstructionArgBuilder::default());
                     Ok(None)
                 }
+                Token::Word(w) if w.starts_with('.') => match directive_syntax(&w) {
+                    None => Err(StatementParserError::UnknownDirective(w)),
+                    Some((Some(0), _)) => parse_directive(w, Vec::new()).map(Some),
+                    Some(..) => {
+                        self.state = StatementParserState::Directive(w, Vec::new());
+                        Ok(None)
+                    }
+                },
+                Token::Word(w) if self.macros.contains_key(&w) => {
+                    let arity = self.macros[&w];
+                    if arity == 0 {
+                        self.last_call = Some((w.clone(), arity));
+                        return Ok(Some(Statement::MacroCall {
+                            name: w,
+                            args: Vec::new(),
+                        }));
+                    }
+
+                    self.state = StatementParserState::MacroCall(
+                        w,
+                        arity,
+                        Vec::new(),
+                        InstructionArgBuilder::default(),
+                    );
+                    Ok(None)
+                }
+                Token::Number(..) | Token::Word(..) if last_call.is_some() => {
+                    let Some((name, arity)) = last_call else {
+                        unreachable!()
+                    };
+
+                    self.state = StatementParserState::ExtraMacroArgs(name, arity, arity + 1);
+                    Ok(None)
+                }
                 token => Err(StatementParserError::UnexpectedToken(token)),
             },
+            StatementParserState::Directive(directive, args) => {
+                let (arity, _) = directive_syntax(directive).unwrap_or_default();
+                match (token, arity) {
+                    (Token::Newline, None) => (),
+                    (Token::Comment(text), None) => self.comment = Some(text),
+                    (Token::Newline | Token::Comment(..), Some(..)) => {
+                        return Err(malformed_directive(directive.clone()))
+                    }
+                    (token, arity) => {
+                        args.push(token);
+                        if arity.is_none_or(|arity| args.len() < arity) {
+                            return Ok(None);
+                        }
+                    }
+                }
+
+                let StatementParserState::Directive(directive, args) =
+                    std::mem::replace(&mut self.state, StatementParserState::Init)
+                else {
+                    unreachable!();
+                };
+
+                let statement = parse_directive(directive, args)?;
+                if let Statement::MacroStart { name, params } = &statement {
+                    self.macros.entry(name.clone()).or_insert(params.len());
+                }
+                Ok(Some(statement))
+            }
+            StatementParserState::MacroCall(name, arity, args, builder) => {
+                if let (Token::Newline | Token::Comment(..), InstructionArgBuilder::Empty) =
+                    (&token, &builder)
+                {
+                    return Err(StatementParserError::MacroArguments {
+                        name: name.clone(),
+                        expected: *arity,
+                        got: args.len(),
+                    });
+                }
+
+                if let Some(arg) = builder.push(token)? {
+                    args.push(arg);
+                    if args.len() < *arity {
+                        return Ok(None);
+                    }
+
+                    let StatementParserState::MacroCall(name, arity, args, _) =
+                        std::mem::replace(&mut self.state, StatementParserState::Init)
+                    else {
+                        unreachable!();
+                    };
+
+                    self.last_call = Some((name.clone(), arity));
+                    return Ok(Some(Statement::MacroCall { name, args }));
+                }
+
+                Ok(None)
+            }
+            StatementParserState::ExtraMacroArgs(name, arity, got) => match token {
+                Token::Number(..) | Token::Word(..) => {
+                    *got += 1;
+                    Ok(None)
+                }
+                Token::Colon => Ok(None),
+                _ => Err(StatementParserError::MacroArguments {
+                    name: name.clone(),
+                    expected: *arity,
+                    got: *got,
+                }),
+            },
             StatementParserState::LabelStart => match token {
                 Token::Word(w) => {
                     self.state = StatementParserState::Init;
@@ -210,6 +533,10 @@ where
 {
     type Item = LocalizedRingsResult<Statement>;
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(carry) = self.carry.take() {
+            return Some(carry);
+        }
+
         if self.done {
             return None;
         }
@@ -218,36 +545,72 @@ where
             let (localized, token) = match self.tokens.next() {
                 None => {
                     self.done = true;
-                    match self.state {
-                        StatementParserState::Init => return None,
-                        _ => {
-                            return Some(
-                                self.last_location
-                                    .transform(Err(StatementParserError::UnclosedStatement.into())),
-                            )
+                    let error = match std::mem::take(&mut self.state) {
+                        StatementParserState::Init | StatementParserState::Recover => return None,
+                        StatementParserState::MacroCall(name, expected, args, _) => {
+                            StatementParserError::MacroArguments {
+                                name,
+                                expected,
+                                got: args.len(),
+                            }
                         }
-                    }
+                        StatementParserState::ExtraMacroArgs(name, expected, got) => {
+                            StatementParserError::MacroArguments {
+                                name,
+                                expected,
+                                got,
+                            }
+                        }
+                        _ => StatementParserError::UnclosedStatement,
+                    };
+                    return Some(self.last_location.transform(Err(error.into())));
                 }
-                Some(loc) => match std::ops::Try::branch(loc) {
-                    std::ops::ControlFlow::Break(v) => {
-                        self.done = true;
-                        return Some(std::ops::FromResidual::from_residual(v));
+                Some(loc) => match loc.transpose() {
+                    Ok(v) => v,
+                    Err(e) => {
+                        // Drop the statement in progress, the tokenizer resumes on the next line
+                        self.state = StatementParserState::Recover;
+                        return Some(e.map(Err));
                     }
-                    std::ops::ControlFlow::Continue(v) => v,
                 },
             }
             .cut();
 
+            let newline = matches!(token, Token::Newline);
+            // Directives taking the rest of the line end at the newline or comment
+            let ends_directive = matches!(
+                (&self.state, &token),
+                (StatementParserState::Directive(..), Token::Comment(..))
+            );
+            let end = if newline || ends_directive {
+                localized.start()
+            } else {
+                localized.end
+            };
+
             if let StatementParserState::Init = self.state {
-                self.last_location = localized;
+                self.last_location = localized.clone();
             }
 
+            // Statements span from their first to their last token
             match self.consume(token) {
-                Ok(Some(statement)) => return Some(self.last_location.transform(Ok(statement))),
+                Ok(Some(statement)) => {
+                    if let Some(text) = self.comment.take() {
+                        self.carry = Some(localized.transform(Ok(Statement::Comment(text))));
+                    }
+
+                    return Some(self.last_location.transform(Ok(statement)).with_end(end));
+                }
                 Ok(None) => (),
                 Err(e) => {
-                    self.done = true;
-                    return Some(self.last_location.transform(Err(e.into())));
+                    // Resynchronize at the end of the line
+                    self.comment = None;
+                    self.state = if newline {
+                        StatementParserState::Init
+                    } else {
+                        StatementParserState::Recover
+                    };
+                    return Some(self.last_location.transform(Err(e.into())).with_end(end));
                 }
             };
         }
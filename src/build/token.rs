@@ -9,6 +9,13 @@ pub enum TokenizerError {
     InvalidDigit(NumberSystem, char),
     NumberOutOfRange(NumberSystem, usize),
     UnfinishedToken,
+    /// Unknown escape sequence in a character or string literal, starting after the `\`
+    InvalidEscape(String),
+    /// A character literal that is not exactly one character
+    InvalidCharacterLiteral(String),
+    NonAsciiCharacter(char),
+    /// A character or string literal that is not closed on its line
+    UnterminatedLiteral,
 }
 
 impl std::error::Error for TokenizerError {}
@@ -20,6 +27,10 @@ impl std::fmt::Display for TokenizerError {
             Self::InvalidDigit(s, d) => write!(f, "Invalid digit for number system {}: {}", s, d),
             Self::NumberOutOfRange(_, n) => write!(f, "Number out of range: {}", n),
             Self::UnfinishedToken => write!(f, "Unfinished token"),
+            Self::InvalidEscape(e) => write!(f, "Invalid escape sequence: \\{}", e),
+            Self::InvalidCharacterLiteral(l) => write!(f, "Invalid character literal: '{}'", l),
+            Self::NonAsciiCharacter(c) => write!(f, "Non-ASCII character in literal: {}", c),
+            Self::UnterminatedLiteral => write!(f, "Unterminated literal"),
         }
     }
 }
@@ -84,16 +95,66 @@ impl std::fmt::Display for WordPrimitive {
 pub enum Token {
     Colon,
     Word(String),
-    Number(u8),
+    /// A number, the number system it was written in and the number of digits it was written
+    /// with, leading zeros included
+    Number(u8, NumberSystem, usize),
     Newline,
     InstructionPrimitive(InstructionPrimitive),
+    /// Text of a comment after the `#`, only emitted by [`Tokenizer::with_comments`]
+    Comment(String),
+    /// A string literal, with its escape sequences decoded
+    String(Vec<u8>),
+}
+
+/// Decodes the escape sequence following a `\`, e.g. `n` or `x41`
+pub fn unescape<I>(chars: &mut I) -> Option<u8>
+where
+    I: Iterator<Item = char>,
+{
+    match chars.next()? {
+        'n' => Some(b'\n'),
+        't' => Some(b'\t'),
+        'r' => Some(b'\r'),
+        '0' => Some(0),
+        c @ ('\\' | '\'' | '"') => Some(c as u8),
+        'x' => {
+            let digits: String = chars.take(2).collect();
+            match digits.len() {
+                2 => u8::from_str_radix(&digits, 16).ok(),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Decodes the text of a character or string literal
+fn decode(text: &str) -> TokenizerResult<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let byte = match c {
+            '\\' => {
+                let rest = chars.as_str();
+                let len = if rest.starts_with('x') { 3 } else { 1 };
+                unescape(&mut chars).ok_or_else(|| {
+                    TokenizerError::InvalidEscape(rest.chars().take(len).collect())
+                })?
+            }
+            c if c.is_ascii() => c as u8,
+            c => return Err(TokenizerError::NonAsciiCharacter(c)),
+        };
+        bytes.push(byte);
+    }
+
+    Ok(bytes)
 }
 
 #[derive(Default)]
 enum TokenizerState {
     #[default]
     Init,
-    Comment,
+    Comment(String),
     /// A number has been detected with a leading zero
     Leading0,
     /// A number of a known system is being built, but is not yet complete
@@ -102,14 +163,34 @@ enum TokenizerState {
     Number(usize, NumberSystem),
     /// Word.. A sequence of characters
     Word(WordPrimitive),
+    /// A character or string literal is being built: the quote, the text so far,
+    /// and whether the last character was an escaping backslash
+    Quoted(char, String, bool),
+    /// A character literal has been closed
+    CharLiteral(u8),
+    /// A string literal has been closed
+    StringLiteral(Vec<u8>),
+    /// An error has been reported, skipping the rest of the line
+    Recover,
+}
+
+/// Characters that continue a number as a constant expression
+fn is_operator(c: char) -> bool {
+    matches!(
+        c,
+        '+' | '-' | '*' | '/' | '%' | '&' | '|' | '^' | '<' | '>' | ')'
+    )
 }
 
 pub struct Tokenizer<I> {
     state: TokenizerState,
+    /// Characters of the token being built
+    raw: String,
     carry: Option<LocalizedRingsResult<Token>>,
     last_location: Localized<()>,
     src: I,
     done: bool,
+    keep_comments: bool,
 }
 
 impl<I> Tokenizer<I>
@@ -119,10 +200,20 @@ where
     pub fn new(src: I) -> Self {
         Self {
             state: TokenizerState::default(),
+            raw: String::new(),
             carry: None,
             src,
             done: false,
             last_location: Localized::default(),
+            keep_comments: false,
+        }
+    }
+
+    /// A tokenizer that emits comments as [`Token::Comment`], e.g. for formatting
+    pub fn with_comments(src: I) -> Self {
+        Self {
+            keep_comments: true,
+            ..Self::new(src)
         }
     }
 
@@ -130,7 +221,7 @@ where
         match &mut self.state {
             TokenizerState::Init => match c {
                 '#' => {
-                    self.state = TokenizerState::Comment;
+                    self.state = TokenizerState::Comment(String::new());
                     Ok(None)
                 }
                 ':' => Ok(Some(Token::Colon)),
@@ -144,12 +235,32 @@ where
                     Ok(None)
                 }
                 c if c.is_whitespace() => Ok(None),
+                '\'' | '"' => {
+                    self.state = TokenizerState::Quoted(c, String::new(), false);
+                    Ok(None)
+                }
                 c => {
                     self.state = TokenizerState::Word(WordPrimitive::Char(c));
                     Ok(None)
                 }
             },
-            TokenizerState::Comment => match c {
+            TokenizerState::Comment(text) => match c {
+                '\n' => {
+                    let TokenizerState::Comment(text) =
+                        std::mem::replace(&mut self.state, TokenizerState::Init)
+                    else {
+                        unreachable!()
+                    };
+
+                    let text = text.trim_end().to_owned();
+                    Ok(self.keep_comments.then_some(Token::Comment(text)))
+                }
+                c => {
+                    text.push(c);
+                    Ok(None)
+                }
+            },
+            TokenizerState::Recover => match c {
                 '\n' => {
                     self.state = TokenizerState::Init;
                     Ok(None)
@@ -173,7 +284,11 @@ where
                 '8' | '9' => Err(TokenizerError::InvalidDigit(NumberSystem::Octal, c)),
                 c if c.is_whitespace() => {
                     self.state = TokenizerState::Init;
-                    Ok(Some(Token::Number(0)))
+                    Ok(Some(Token::Number(0, NumberSystem::Decimal, 1)))
+                }
+                c if is_operator(c) => {
+                    self.state = TokenizerState::Word(WordPrimitive::String(format!("0{}", c)));
+                    Ok(None)
                 }
                 _ => Err(TokenizerError::InvalidCharacter(c)),
             },
@@ -194,6 +309,14 @@ where
                                     Ok(None)
                                 }
                             )+
+                            c if is_operator(c) => {
+                                // A constant expression starting with a number, e.g. 0x10|3
+                                self.state = TokenizerState::Word(WordPrimitive::String(format!(
+                                    "{}{}",
+                                    self.raw, c
+                                )));
+                                Ok(None)
+                            }
                             _ => Err(TokenizerError::InvalidCharacter(c)),
                         }
                     };
@@ -209,7 +332,7 @@ where
                         'A'..='F' => c as usize - 'A' as usize + 10,
                         'a'..='f' => c as usize - 'a' as usize + 10,
                     ),
-                    NumberSystem::Unknown => unreachable!(),
+                    NumberSystem::Unknown | NumberSystem::Character => unreachable!(),
                 }
             }
             TokenizerState::Number(val, sys) => {
@@ -230,16 +353,30 @@ where
                                 }
                             )+
                             c if c.is_whitespace() => {
-                                let number = *val;
+                                let (number, sys) = (*val, *sys);
                                 if number > u8::MAX as usize {
                                     return Err(TokenizerError::NumberOutOfRange(
                                         NumberSystem::Binary,
                                         number,
                                     ));
                                 }
+                                let prefix = match sys {
+                                    NumberSystem::Binary | NumberSystem::Hexadecimal => 2,
+                                    NumberSystem::Octal => 1,
+                                    _ => 0,
+                                };
+                                let digits = self.raw.len() - prefix;
                                 self.state = TokenizerState::Init;
-                                Ok(Some(Token::Number(number as u8)))
+                                Ok(Some(Token::Number(number as u8, sys, digits)))
                             },
+                            c if is_operator(c) => {
+                                // A constant expression starting with a number, e.g. 0x10|3
+                                self.state = TokenizerState::Word(WordPrimitive::String(format!(
+                                    "{}{}",
+                                    self.raw, c
+                                )));
+                                Ok(None)
+                            }
                             _ => Err(TokenizerError::InvalidCharacter(c)),
                         }
                     };
@@ -255,9 +392,61 @@ where
                         'A'..='F' => c as usize - 'A' as usize + 10,
                         'a'..='f' => c as usize - 'a' as usize + 10,
                     ),
-                    NumberSystem::Unknown => unreachable!(),
+                    NumberSystem::Unknown | NumberSystem::Character => unreachable!(),
                 }
             }
+            TokenizerState::Quoted(quote, text, escaped) => match c {
+                '\n' => Err(TokenizerError::UnterminatedLiteral),
+                c if *escaped => {
+                    text.push(c);
+                    *escaped = false;
+                    Ok(None)
+                }
+                '\\' => {
+                    text.push(c);
+                    *escaped = true;
+                    Ok(None)
+                }
+                c if c == *quote => {
+                    let bytes = decode(text)?;
+                    self.state = match (c, bytes.as_slice()) {
+                        ('"', _) => TokenizerState::StringLiteral(bytes),
+                        (_, [byte]) => TokenizerState::CharLiteral(*byte),
+                        _ => return Err(TokenizerError::InvalidCharacterLiteral(text.clone())),
+                    };
+                    Ok(None)
+                }
+                c => {
+                    text.push(c);
+                    Ok(None)
+                }
+            },
+            TokenizerState::CharLiteral(byte) => match c {
+                c if c.is_whitespace() => {
+                    let byte = *byte;
+                    self.state = TokenizerState::Init;
+                    Ok(Some(Token::Number(byte, NumberSystem::Character, 1)))
+                }
+                c if is_operator(c) => {
+                    // A constant expression starting with a character, e.g. 'A'+1
+                    self.state =
+                        TokenizerState::Word(WordPrimitive::String(format!("{}{}", self.raw, c)));
+                    Ok(None)
+                }
+                _ => Err(TokenizerError::InvalidCharacter(c)),
+            },
+            TokenizerState::StringLiteral(..) => match c {
+                c if c.is_whitespace() => {
+                    let TokenizerState::StringLiteral(bytes) =
+                        std::mem::replace(&mut self.state, TokenizerState::Init)
+                    else {
+                        unreachable!()
+                    };
+
+                    Ok(Some(Token::String(bytes)))
+                }
+                _ => Err(TokenizerError::InvalidCharacter(c)),
+            },
             TokenizerState::Word(w) => match c {
                 c if c.is_whitespace() => {
                     let TokenizerState::Word(w) =
@@ -307,7 +496,7 @@ where
             let localized = match self.src.next() {
                 None => {
                     self.done = true;
-                    return if let TokenizerState::Init = self.state {
+                    return if let TokenizerState::Init | TokenizerState::Recover = self.state {
                         None
                     } else {
                         Some(
@@ -316,25 +505,46 @@ where
                         )
                     };
                 }
-                Some(loc) => match std::ops::Try::branch(loc) {
-                    std::ops::ControlFlow::Break(v) => {
+                Some(loc) => match loc.transpose() {
+                    Ok(v) => v,
+                    Err(e) => {
                         self.done = true;
-                        return Some(std::ops::FromResidual::from_residual(v));
+                        return Some(e.map(Err));
                     }
-                    std::ops::ControlFlow::Continue(v) => v,
                 },
             };
 
             if let TokenizerState::Init = self.state {
                 self.last_location = localized.transform(());
+                self.raw.clear();
             }
 
-            let maybe_token = match self.consume(localized.value) {
-                Ok(Some(token)) => Some(self.last_location.transform(Ok(token))),
+            let consumed = self.consume(localized.value);
+            self.raw.push(localized.value);
+            let maybe_token = match consumed {
+                Ok(Some(token)) => {
+                    // Apart from colons, tokens end at the character terminating them
+                    let end = match token {
+                        Token::Colon => localized.end,
+                        _ => localized.start(),
+                    };
+                    Some(self.last_location.transform(Ok(token)).with_end(end))
+                }
                 Ok(None) => None,
                 Err(e) => {
-                    self.done = true;
-                    return Some(self.last_location.transform(Err(e.into())));
+                    // Span up to and including the offending character
+                    let end = match localized.value {
+                        '\n' => localized.start(),
+                        _ => localized.end,
+                    };
+                    // Resynchronize at the end of the line
+                    self.state = if localized.value == '\n' {
+                        self.carry = Some(localized.transform(Ok(Token::Newline)));
+                        TokenizerState::Init
+                    } else {
+                        TokenizerState::Recover
+                    };
+                    return Some(self.last_location.transform(Err(e.into())).with_end(end));
                 }
             };
 
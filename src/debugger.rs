@@ -0,0 +1,473 @@
+use std::{collections::BTreeMap, io::Write};
+
+use crate::{
+    build::Program,
+    instruction::Label,
+    io::RingsIo,
+    vm::{ExitCode, RingId, RingsVM, EXIT_RUNTIME_ERROR},
+    MaybeLocalized, SourceId, Sources,
+};
+
+type DebuggerResult<T> = Result<T, DebuggerError>;
+#[derive(Debug)]
+pub enum DebuggerError {
+    UnknownCommand(String),
+    MissingArgument(&'static str),
+    InvalidNumber(String),
+    LabelNotFound(String),
+    NoInstructionOnLine(usize),
+    BreakpointNotFound(usize),
+    InvalidRing(RingId),
+}
+
+impl std::error::Error for DebuggerError {}
+
+impl std::fmt::Display for DebuggerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnknownCommand(c) => write!(f, "Unknown command: {}. Try \"help\"", c),
+            Self::MissingArgument(a) => write!(f, "Missing argument: {}", a),
+            Self::InvalidNumber(n) => write!(f, "Invalid number: {}", n),
+            Self::LabelNotFound(l) => write!(f, "Label not found: {}", l),
+            Self::NoInstructionOnLine(l) => write!(f, "No instruction on line {}", l),
+            Self::BreakpointNotFound(b) => write!(f, "No breakpoint number {}", b),
+            Self::InvalidRing(r) => write!(f, "Invalid ring {}", r),
+        }
+    }
+}
+
+const HELP: &str = "\
+Commands:
+    break, b <:label|line>  Set a breakpoint on a label or a source line
+    delete, d <n>           Delete breakpoint number n
+    info, i                 List breakpoints
+    step, s [n]             Execute n instructions (default 1)
+    next, n                 Execute until the next source line
+    continue, c             Run until a breakpoint is hit or the program halts
+    print, p <ring>         Print a ring with its rotation offset
+    rings, r                Print all rings
+    list, l                 Show the source around the current instruction
+    restart                 Restart the program
+    help, h                 Show this help
+    quit, q                 Exit the debugger
+An empty line repeats the last command.";
+
+#[derive(Clone, Debug)]
+pub enum BreakpointTarget {
+    Label(String),
+    Line(usize),
+}
+
+impl std::fmt::Display for BreakpointTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Label(l) => write!(f, ":{}", l),
+            Self::Line(l) => write!(f, "line {}", l),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Breakpoint {
+    pub target: BreakpointTarget,
+    pub address: Label,
+}
+
+#[derive(Clone, Debug)]
+pub enum DebuggerCommand {
+    Break(BreakpointTarget),
+    Delete(usize),
+    Info,
+    Step(usize),
+    Next,
+    Continue,
+    Print(RingId),
+    Rings,
+    List,
+    Restart,
+    Help,
+    Quit,
+}
+
+impl std::str::FromStr for DebuggerCommand {
+    type Err = DebuggerError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        fn number<T: std::str::FromStr>(
+            arg: Option<&str>,
+            name: &'static str,
+        ) -> DebuggerResult<T> {
+            let arg = arg.ok_or(DebuggerError::MissingArgument(name))?;
+            arg.parse()
+                .map_err(|_| DebuggerError::InvalidNumber(arg.to_owned()))
+        }
+
+        let mut words = s.split_whitespace();
+        let Some(command) = words.next() else {
+            return Err(DebuggerError::MissingArgument("command"));
+        };
+        let arg = words.next();
+
+        Ok(match command {
+            "break" | "b" => {
+                let target = arg.ok_or(DebuggerError::MissingArgument("label or line"))?;
+                match target.strip_prefix(':') {
+                    Some(label) => Self::Break(BreakpointTarget::Label(label.to_owned())),
+                    None if target.starts_with(|c: char| c.is_ascii_digit()) => {
+                        Self::Break(BreakpointTarget::Line(number(arg, "line")?))
+                    }
+                    None => Self::Break(BreakpointTarget::Label(target.to_owned())),
+                }
+            }
+            "delete" | "d" => Self::Delete(number(arg, "breakpoint number")?),
+            "info" | "i" => Self::Info,
+            "step" | "s" => match arg {
+                None => Self::Step(1),
+                arg => Self::Step(number(arg, "steps")?),
+            },
+            "next" | "n" => Self::Next,
+            "continue" | "c" => Self::Continue,
+            "print" | "p" => Self::Print(number(arg, "ring")?),
+            "rings" | "r" => Self::Rings,
+            "list" | "l" => Self::List,
+            "restart" => Self::Restart,
+            "help" | "h" => Self::Help,
+            "quit" | "q" => Self::Quit,
+            c => return Err(DebuggerError::UnknownCommand(c.to_owned())),
+        })
+    }
+}
+
+/// Interactive debugger over a localized [`Program`]
+pub struct Debugger {
+    vm: RingsVM,
+    /// Lines of each source file, indexed by [`SourceId`]
+    sources: Vec<Vec<String>>,
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    next_breakpoint: usize,
+    /// Set by a runtime error, the program cannot continue until it is restarted
+    failed: bool,
+}
+
+impl Debugger {
+    /// `source` is the text the program was assembled from, used for showing source lines
+    pub fn new(program: Program, source: &str) -> Self {
+        Self::from_lines(program, vec![Self::lines(source)])
+    }
+
+    /// Debugs a program assembled from several files, e.g. with includes.
+    /// Line breakpoints refer to the main file.
+    pub fn with_sources(program: Program, sources: &Sources) -> Self {
+        let lines = sources.iter().map(|(_, file)| Self::lines(&file.text));
+        Self::from_lines(program, lines.collect())
+    }
+
+    fn from_lines(program: Program, sources: Vec<Vec<String>>) -> Self {
+        Self {
+            vm: RingsVM::new(program),
+            sources,
+            breakpoints: BTreeMap::new(),
+            next_breakpoint: 1,
+            failed: false,
+        }
+    }
+
+    fn lines(source: &str) -> Vec<String> {
+        source.lines().map(str::to_owned).collect()
+    }
+
+    pub fn vm(&self) -> &RingsVM {
+        &self.vm
+    }
+
+    pub fn breakpoints(&self) -> &BTreeMap<usize, Breakpoint> {
+        &self.breakpoints
+    }
+
+    fn line_at(&self, address: usize) -> Option<(SourceId, usize)> {
+        match self.vm.program().get(address)? {
+            MaybeLocalized::Localized(l) => Some((l.source, l.line_number)),
+            MaybeLocalized::General(..) => None,
+        }
+    }
+
+    fn current_line(&self) -> Option<(SourceId, usize)> {
+        self.line_at(self.vm.pc)
+    }
+
+    fn resolve(&self, target: &BreakpointTarget) -> DebuggerResult<Label> {
+        match target {
+            BreakpointTarget::Label(label) => self
+                .vm
+                .program()
+                .labels()
+                .and_then(|labels| labels.get(label))
+                .copied()
+                .ok_or_else(|| DebuggerError::LabelNotFound(label.clone())),
+            BreakpointTarget::Line(line) => (0..self.vm.program().len())
+                .find(|address| self.line_at(*address) == Some((SourceId(0), *line)))
+                .ok_or(DebuggerError::NoInstructionOnLine(*line)),
+        }
+    }
+
+    /// Adds a breakpoint and returns its number
+    pub fn add_breakpoint(&mut self, target: BreakpointTarget) -> DebuggerResult<usize> {
+        let address = self.resolve(&target)?;
+        let number = self.next_breakpoint;
+        self.next_breakpoint += 1;
+        self.breakpoints
+            .insert(number, Breakpoint { target, address });
+        Ok(number)
+    }
+
+    pub fn remove_breakpoint(&mut self, number: usize) -> DebuggerResult<Breakpoint> {
+        self.breakpoints
+            .remove(&number)
+            .ok_or(DebuggerError::BreakpointNotFound(number))
+    }
+
+    fn at_breakpoint(&self, vm: &RingsVM) -> bool {
+        self.breakpoints.values().any(|b| b.address == vm.pc)
+    }
+
+    fn write_source_line<W>(
+        &self,
+        out: &mut W,
+        (source, line): (SourceId, usize),
+        marker: &str,
+    ) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        let text = self
+            .sources
+            .get(source.0)
+            .and_then(|lines| lines.get(line.wrapping_sub(1)))
+            .map(String::as_str)
+            .unwrap_or("");
+        writeln!(out, "{:>2} {:>4} | {}", marker, line, text)
+    }
+
+    fn write_location<W>(&self, out: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        if let Some(code) = self.vm.exit_code {
+            return writeln!(out, "Program halted with exit code {}", code);
+        }
+
+        match self.vm.program().instruction_text(self.vm.pc) {
+            Some(instr) => {
+                writeln!(out, "pc {:04}: {}", self.vm.pc, instr)?;
+                match self.current_line() {
+                    Some(line) => self.write_source_line(out, line, "=>"),
+                    None => Ok(()),
+                }
+            }
+            None => writeln!(out, "pc {:04}: end of program", self.vm.pc),
+        }
+    }
+
+    /// Steps the VM while `stop` returns false. Runtime errors are reported and leave the
+    /// program counter at the faulting instruction.
+    fn advance<I, W, P>(&mut self, io: &mut I, out: &mut W, mut stop: P) -> std::io::Result<()>
+    where
+        I: RingsIo,
+        W: Write,
+        P: FnMut(&Self, &RingsVM) -> bool,
+    {
+        if self.failed {
+            return writeln!(
+                out,
+                "The program stopped on a runtime error, restart it to continue"
+            );
+        }
+
+        loop {
+            match self.vm.step(io).err() {
+                Some(e) => {
+                    self.failed = true;
+                    out.flush()?;
+                    return writeln!(out, "Runtime error {}", self.vm.diagnostics(e));
+                }
+                None if self.vm.halted() || (stop)(self, &self.vm) => {
+                    io.flush();
+                    return self.write_location(out);
+                }
+                None => (),
+            }
+        }
+    }
+
+    /// Executes a single command. Returns `false` when the debugger should exit.
+    pub fn execute<I, W>(
+        &mut self,
+        command: &DebuggerCommand,
+        io: &mut I,
+        out: &mut W,
+    ) -> std::io::Result<bool>
+    where
+        I: RingsIo,
+        W: Write,
+    {
+        match command {
+            DebuggerCommand::Break(target) => match self.add_breakpoint(target.clone()) {
+                Ok(n) => writeln!(out, "Breakpoint {} at {}", n, target)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            DebuggerCommand::Delete(n) => match self.remove_breakpoint(*n) {
+                Ok(b) => writeln!(out, "Deleted breakpoint {} at {}", n, b.target)?,
+                Err(e) => writeln!(out, "{}", e)?,
+            },
+            DebuggerCommand::Info => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "No breakpoints")?;
+                }
+
+                for (n, b) in self.breakpoints.iter() {
+                    writeln!(out, "{:>3}: {} (pc {:04})", n, b.target, b.address)?;
+                }
+            }
+            DebuggerCommand::Step(0) => self.write_location(out)?,
+            DebuggerCommand::Step(n) => {
+                let mut remaining = *n;
+                self.advance(io, out, |_, _| {
+                    remaining = remaining.saturating_sub(1);
+                    remaining == 0
+                })?
+            }
+            DebuggerCommand::Next => {
+                let line = self.current_line();
+                self.advance(io, out, |dbg, vm| {
+                    dbg.at_breakpoint(vm) || dbg.line_at(vm.pc) != line
+                })?
+            }
+            DebuggerCommand::Continue => self.advance(io, out, Self::at_breakpoint)?,
+            DebuggerCommand::Print(ring) => match self.vm.rings.get(*ring as usize) {
+                Some(r) => writeln!(out, "ring {}: {}", ring, r)?,
+                None => writeln!(out, "{}", DebuggerError::InvalidRing(*ring))?,
+            },
+            DebuggerCommand::Rings => {
+                if self.vm.rings.is_empty() {
+                    writeln!(out, "No rings")?;
+                }
+
+                for (i, r) in self.vm.rings.iter().enumerate() {
+                    writeln!(out, "ring {}: {}", i, r)?;
+                }
+            }
+            DebuggerCommand::List => match self.current_line() {
+                Some((source, current)) => {
+                    let len = self.sources.get(source.0).map_or(0, Vec::len);
+                    for line in current.saturating_sub(2).max(1)..=current + 2 {
+                        if line > len {
+                            break;
+                        }
+
+                        let marker = if line == current { "=>" } else { "" };
+                        self.write_source_line(out, (source, line), marker)?;
+                    }
+                }
+                None => self.write_location(out)?,
+            },
+            DebuggerCommand::Restart => {
+                self.vm.reset();
+                self.failed = false;
+                self.write_location(out)?;
+            }
+            DebuggerCommand::Help => writeln!(out, "{}", HELP)?,
+            DebuggerCommand::Quit => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    /// Reads commands from `commands`, one per line, e.g. [`std::io::BufRead::lines`], until it is
+    /// exhausted or `quit` is entered. Returns the exit code if the program halted, or
+    /// [`EXIT_RUNTIME_ERROR`] if it stopped on a runtime error.
+    pub fn repl<I, C, W>(
+        &mut self,
+        io: &mut I,
+        commands: C,
+        mut out: W,
+    ) -> std::io::Result<Option<ExitCode>>
+    where
+        I: RingsIo,
+        C: IntoIterator<Item = std::io::Result<String>>,
+        W: Write,
+    {
+        self.write_location(&mut out)?;
+
+        let mut last: Option<DebuggerCommand> = None;
+        let mut lines = commands.into_iter();
+        loop {
+            write!(out, "(rings) ")?;
+            out.flush()?;
+
+            let Some(line) = lines.next() else {
+                break;
+            };
+            let line = line?;
+
+            let command = if line.trim().is_empty() {
+                match &last {
+                    Some(c) => c.clone(),
+                    None => continue,
+                }
+            } else {
+                match line.parse() {
+                    Ok(c) => c,
+                    Err(e) => {
+                        writeln!(out, "{}", e)?;
+                        continue;
+                    }
+                }
+            };
+
+            if !self.execute(&command, io, &mut out)? {
+                break;
+            }
+
+            last = Some(command);
+        }
+
+        if self.failed {
+            return Ok(Some(EXIT_RUNTIME_ERROR));
+        }
+        Ok(self.vm.exit_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Debugger;
+    use crate::{build::ProgramAssembler, io::BufferIo, vm::EXIT_RUNTIME_ERROR};
+
+    /// Runs `commands` in the debugger, returning its output and exit code
+    fn debug(source: &str, commands: &[&str]) -> (String, Option<u8>) {
+        let program = ProgramAssembler::assemble(source.as_bytes(), true).unwrap();
+        let mut debugger = Debugger::new(program, source);
+        let commands = commands.iter().map(|c| Ok(c.to_string()));
+
+        let mut out = Vec::new();
+        let exit_code = debugger
+            .repl(&mut BufferIo::new(""), commands, &mut out)
+            .unwrap();
+        (String::from_utf8(out).unwrap(), exit_code)
+    }
+
+    #[test]
+    fn exits_with_runtime_error_status() {
+        let (out, exit_code) = debug("mkr 1\nout 1\nhlt 0", &["continue"]);
+        assert!(out.contains("Runtime error"));
+        assert_eq!(exit_code, Some(EXIT_RUNTIME_ERROR));
+
+        let (_, exit_code) = debug("mkr 1\nout 1\nhlt 0", &["continue", "restart"]);
+        assert_eq!(exit_code, None);
+    }
+
+    #[test]
+    fn shows_label_names() {
+        let (out, _) = debug(":main\nmkr 1\n:.loop\njmp :.loop", &["step"]);
+        assert!(out.contains("pc 0001: jmp :.loop"), "{}", out);
+    }
+}
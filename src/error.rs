@@ -1,12 +1,18 @@
 use std::fmt::Display;
 
 use crate::{
-    build::{char::CharacterReaderError, statement::StatementParserError, token::TokenizerError, AssemblerError}, vm::RuntimeError, LocalizedResult, MaybeLocalized
+    build::{
+        bytecode::BytecodeError, char::CharacterReaderError, statement::StatementParserError,
+        token::TokenizerError, AssemblerError,
+    },
+    vm::RuntimeError,
+    Localized, LocalizedResult, MaybeLocalized, SourceId,
 };
 
 pub type RingsResult<T> = Result<T, RingsError>;
 pub type LocalizedRingsResult<T> = LocalizedResult<T, RingsError>;
-pub type MaybeLocalizedRingsResult<T> = MaybeLocalized<RingsResult<T>>;
+pub type MaybeLocalizedRingsResult<T> = Result<T, MaybeLocalized<RingsError>>;
+pub type DiagnosticsResult<T> = Result<T, Diagnostics>;
 #[derive(Debug)]
 pub enum RingsError {
     IoError(std::io::Error),
@@ -15,6 +21,7 @@ pub enum RingsError {
     Tokenizer(TokenizerError),
     StatementParser(StatementParserError),
     Assembler(AssemblerError),
+    Bytecode(BytecodeError),
     Runtime(RuntimeError),
 }
 
@@ -48,6 +55,12 @@ impl From<AssemblerError> for RingsError {
     }
 }
 
+impl From<BytecodeError> for RingsError {
+    fn from(value: BytecodeError) -> Self {
+        Self::Bytecode(value)
+    }
+}
+
 impl From<RuntimeError> for RingsError {
     fn from(value: RuntimeError) -> Self {
         Self::Runtime(value)
@@ -63,7 +76,205 @@ impl Display for RingsError {
             Self::Tokenizer(e) => Display::fmt(e, f),
             Self::StatementParser(e) => Display::fmt(e, f),
             Self::Assembler(e) => Display::fmt(e, f),
+            Self::Bytecode(e) => Display::fmt(e, f),
             Self::Runtime(e) => Display::fmt(e, f),
         }
     }
 }
+
+/// An error with an optional hint on how to fix it
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub error: MaybeLocalized<RingsError>,
+    pub hint: Option<String>,
+    /// Other locations involved in the error, e.g. the call site of the macro the error is in
+    pub notes: Vec<Localized<String>>,
+}
+
+impl Diagnostic {
+    pub fn new<E>(error: E, hint: Option<String>) -> Self
+    where
+        E: Into<MaybeLocalized<RingsError>>,
+    {
+        Self {
+            error: error.into(),
+            hint,
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn with_notes(mut self, notes: Vec<Localized<String>>) -> Self {
+        self.notes = notes;
+        self
+    }
+
+    /// Adds notes pointing at the macro calls the error was expanded from, innermost first
+    pub fn with_call_sites(self, call_sites: &[Localized<String>]) -> Self {
+        let notes = call_sites
+            .iter()
+            .rev()
+            .map(|call| call.transform(format!("in this expansion of macro {}", call.value)))
+            .collect();
+        self.with_notes(notes)
+    }
+
+    /// Source, line and character of the error, `None` for errors without a location
+    pub fn location(&self) -> Option<(SourceId, usize, usize)> {
+        match &self.error {
+            MaybeLocalized::General(..) => None,
+            MaybeLocalized::Localized(l) => Some((l.source, l.line_number, l.char_number)),
+        }
+    }
+}
+
+impl Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.error, f)?;
+        if let Some(hint) = &self.hint {
+            write!(f, "\n    hint: {}", hint)?;
+        }
+
+        for note in &self.notes {
+            write!(f, "\n    note {}", note)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A list of errors, ordered by their location
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push<E>(&mut self, error: E)
+    where
+        E: Into<MaybeLocalized<RingsError>>,
+    {
+        self.push_with_hint(error, None);
+    }
+
+    pub fn push_with_hint<E>(&mut self, error: E, hint: Option<String>)
+    where
+        E: Into<MaybeLocalized<RingsError>>,
+    {
+        self.push_diagnostic(Diagnostic::new(error, hint));
+    }
+
+    pub fn push_diagnostic(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.diagnostics.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.diagnostics.len()
+    }
+
+    pub fn iter(&self) -> std::slice::Iter<'_, Diagnostic> {
+        self.diagnostics.iter()
+    }
+
+    /// `Ok(value)` if no error has been reported, otherwise the errors sorted by source and
+    /// location. Errors without a location come first.
+    pub fn into_result<T>(mut self, value: T) -> DiagnosticsResult<T> {
+        if self.is_empty() {
+            return Ok(value);
+        }
+
+        self.diagnostics.sort_by_key(Diagnostic::location);
+        Err(self)
+    }
+}
+
+impl IntoIterator for Diagnostics {
+    type Item = Diagnostic;
+    type IntoIter = std::vec::IntoIter<Self::Item>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.diagnostics.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Diagnostics {
+    type Item = &'a Diagnostic;
+    type IntoIter = std::slice::Iter<'a, Diagnostic>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<E> From<E> for Diagnostics
+where
+    E: Into<MaybeLocalized<RingsError>>,
+{
+    fn from(value: E) -> Self {
+        Self {
+            diagnostics: vec![Diagnostic::new(value, None)],
+        }
+    }
+}
+
+impl std::error::Error for Diagnostics {}
+
+impl Display for Diagnostics {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, diagnostic) in self.diagnostics.iter().enumerate() {
+            if i != 0 {
+                writeln!(f)?;
+            }
+
+            Display::fmt(diagnostic, f)?;
+        }
+
+        if self.diagnostics.len() > 1 {
+            write!(f, "\n{} errors", self.diagnostics.len())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Diagnostics, RingsError};
+    use crate::{build::AssemblerError, Localized, Position, SourceId};
+
+    #[test]
+    fn sorts_by_source_then_location() {
+        let error = |source, line_number| {
+            let position = Position {
+                line_number,
+                char_number: 1,
+                offset: 0,
+            };
+            let error = AssemblerError::LabelNotFound(format!("{}:{}", source, line_number));
+            Localized::at(position, SourceId(source), RingsError::from(error))
+        };
+
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push(error(1, 1));
+        diagnostics.push(error(0, 5));
+        diagnostics.push(RingsError::Assembler(AssemblerError::UnmatchedMacroEnd));
+        diagnostics.push(error(0, 2));
+
+        let diagnostics = diagnostics.into_result(()).unwrap_err();
+        let locations: Vec<_> = diagnostics.iter().map(|d| d.location()).collect();
+        assert_eq!(
+            locations,
+            [
+                None,
+                Some((SourceId(0), 2, 1)),
+                Some((SourceId(0), 5, 1)),
+                Some((SourceId(1), 1, 1)),
+            ]
+        );
+    }
+}
@@ -0,0 +1,392 @@
+use crate::{
+    build::{
+        char::CharIterator,
+        statement::{InstructionArg, InstructionStatement, Statement, StatementParser},
+        token::Tokenizer,
+    },
+    error::{Diagnostics, DiagnosticsResult},
+    NumberSystem,
+};
+
+const INDENT: usize = 4;
+/// Minimum number of spaces between code and a trailing comment
+const COMMENT_GAP: usize = 2;
+
+enum Line {
+    Blank,
+    Code {
+        indent: usize,
+        code: String,
+        comment: Option<String>,
+    },
+}
+
+/// `byte` as it is written inside a literal delimited by `quote`
+fn escape(byte: u8, quote: char) -> String {
+    match byte {
+        b'\n' => "\\n".to_owned(),
+        b'\t' => "\\t".to_owned(),
+        b'\r' => "\\r".to_owned(),
+        0 => "\\0".to_owned(),
+        b'\\' => "\\\\".to_owned(),
+        b if b as char == quote => format!("\\{}", quote),
+        b if b.is_ascii_graphic() || b == b' ' => (b as char).to_string(),
+        b => format!("\\x{:02X}", b),
+    }
+}
+
+fn format_string(text: &[u8]) -> String {
+    let text: String = text.iter().map(|byte| escape(*byte, '"')).collect();
+    format!("\"{}\"", text)
+}
+
+/// `value` in `system`, padded with leading zeros to the number of `digits` it was written with
+fn format_number(value: u8, system: NumberSystem, digits: usize) -> String {
+    match system {
+        NumberSystem::Character => format!("'{}'", escape(value, '\'')),
+        NumberSystem::Hexadecimal => format!("0x{:0digits$X}", value),
+        NumberSystem::Octal => format!("0{:0digits$o}", value),
+        NumberSystem::Binary => format!("0b{:0digits$b}", value),
+        NumberSystem::Decimal | NumberSystem::Unknown => value.to_string(),
+    }
+}
+
+fn format_arg(arg: &InstructionArg) -> String {
+    match arg {
+        InstructionArg::Number(value, system, digits) => format_number(*value, *system, *digits),
+        InstructionArg::Label(label) => format!(":{}", label),
+        InstructionArg::Expression(expression) => expression.clone(),
+    }
+}
+
+fn format_instruction(statement: &InstructionStatement) -> String {
+    let (primitive, args) = match statement {
+        InstructionStatement::Instruction1(p, a) => (p, vec![a]),
+        InstructionStatement::Instruction2(p, a, b) => (p, vec![a, b]),
+        InstructionStatement::Instruction3(p, a, b, c) => (p, vec![a, b, c]),
+    };
+
+    format_call(&primitive.to_string(), &args)
+}
+
+/// An instruction or macro call
+fn format_call(name: &str, args: &[&InstructionArg]) -> String {
+    let mut out = name.to_owned();
+    for arg in args {
+        out.push(' ');
+        out.push_str(&format_arg(arg));
+    }
+
+    out
+}
+
+/// Full-line comments are indented like the code line right after them,
+/// or like the one before them at the end of a block
+fn indent_comments(lines: &mut [Line]) {
+    let mut previous_indent = 0;
+    for line in lines.iter_mut() {
+        match line {
+            Line::Blank => previous_indent = 0,
+            Line::Code { indent, code, .. } if code.is_empty() => *indent = previous_indent,
+            Line::Code { indent, .. } => previous_indent = *indent,
+        }
+    }
+
+    let mut next_indent = None;
+    for line in lines.iter_mut().rev() {
+        match line {
+            Line::Blank => next_indent = None,
+            Line::Code { indent, code, .. } if code.is_empty() => {
+                *indent = next_indent.unwrap_or(*indent)
+            }
+            Line::Code { indent, .. } => next_indent = Some(*indent),
+        }
+    }
+}
+
+/// Writes the lines, aligning trailing comments within each block of lines between blank lines
+fn render(lines: &[Line]) -> String {
+    let mut out = String::new();
+
+    for block in lines.split(|line| matches!(line, Line::Blank)) {
+        if block.is_empty() {
+            continue;
+        }
+
+        if !out.is_empty() {
+            out.push('\n');
+        }
+
+        let column = block
+            .iter()
+            .filter_map(|line| match line {
+                Line::Code {
+                    indent,
+                    code,
+                    comment: Some(_),
+                } if !code.is_empty() => Some(indent + code.chars().count() + COMMENT_GAP),
+                _ => None,
+            })
+            .max()
+            .unwrap_or_default();
+
+        for line in block {
+            let Line::Code {
+                indent,
+                code,
+                comment,
+            } = line
+            else {
+                continue;
+            };
+
+            let mut text = format!("{}{}", " ".repeat(*indent), code);
+            if let Some(comment) = comment {
+                if !code.is_empty() {
+                    let width = text.chars().count();
+                    text.push_str(&" ".repeat(column - width));
+                }
+
+                text.push('#');
+                text.push_str(comment);
+            }
+
+            out.push_str(&text);
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+/// Formats Rings source in the canonical layout: labels at column 0 on their own line,
+/// instructions indented by 4 spaces, with the instructions of one source line kept on one line,
+/// trailing comments aligned within each block of lines, and at most one blank line in a row.
+/// Numbers are kept in the number system they were written in.
+///
+/// Returns the errors if the source cannot be parsed.
+pub fn format_source<R>(reader: R) -> DiagnosticsResult<String>
+where
+    R: std::io::Read,
+{
+    let chars = CharIterator::new(reader);
+    let statements = StatementParser::new(Tokenizer::with_comments(chars));
+
+    let mut diagnostics = Diagnostics::new();
+    let mut lines = Vec::new();
+    // Source line of the last statement, and whether instructions can be appended to the last line
+    let mut last_line: Option<usize> = None;
+    let mut open_instruction = false;
+
+    for statement in statements {
+        let statement = match statement.transpose() {
+            Ok(statement) => statement,
+            Err(e) => {
+                diagnostics.push(e);
+                continue;
+            }
+        };
+
+        let line_number = statement.line_number;
+        let same_line = last_line == Some(line_number);
+        if !same_line {
+            if last_line.is_some_and(|last| line_number > last + 1) {
+                lines.push(Line::Blank);
+            }
+
+            last_line = Some(line_number);
+            open_instruction = false;
+        }
+
+        match statement.value {
+            Statement::Label(label) => {
+                lines.push(Line::Code {
+                    indent: 0,
+                    code: format!(":{}", label),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::Instruction(..) | Statement::MacroCall { .. } => {
+                let text = match &statement.value {
+                    Statement::Instruction(instruction) => format_instruction(instruction),
+                    Statement::MacroCall { name, args } => {
+                        format_call(name, &args.iter().collect::<Vec<_>>())
+                    }
+                    _ => unreachable!(),
+                };
+                match lines.last_mut() {
+                    Some(Line::Code { code, .. }) if open_instruction => {
+                        code.push(' ');
+                        code.push_str(&text);
+                    }
+                    _ => lines.push(Line::Code {
+                        indent: INDENT,
+                        code: text,
+                        comment: None,
+                    }),
+                }
+                open_instruction = true;
+            }
+            Statement::RingAlias { name, ring } => {
+                lines.push(Line::Code {
+                    indent: 0,
+                    code: format!(".ring {} = {}", name, format_arg(&ring)),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::Constant { name, value } => {
+                lines.push(Line::Code {
+                    indent: 0,
+                    code: format!(".equ {} {}", name, format_arg(&value)),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::Print { ring, text } => {
+                lines.push(Line::Code {
+                    indent: INDENT,
+                    code: format!(".print {} {}", format_arg(&ring), format_string(&text)),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::Data { ring, text } => {
+                lines.push(Line::Code {
+                    indent: INDENT,
+                    code: format!(".data {} {}", format_arg(&ring), format_string(&text)),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::MacroStart { name, params } => {
+                let mut code = format!(".macro {}", name);
+                for param in params {
+                    code.push(' ');
+                    code.push_str(&param);
+                }
+
+                lines.push(Line::Code {
+                    indent: 0,
+                    code,
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::MacroEnd => {
+                lines.push(Line::Code {
+                    indent: 0,
+                    code: ".endm".to_owned(),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::Include(path) => {
+                lines.push(Line::Code {
+                    indent: 0,
+                    code: format!(".include {}", format_string(path.as_bytes())),
+                    comment: None,
+                });
+                open_instruction = false;
+            }
+            Statement::Comment(text) => match lines.last_mut() {
+                Some(Line::Code { comment, .. }) if same_line => *comment = Some(text),
+                _ => lines.push(Line::Code {
+                    indent: 0,
+                    code: String::new(),
+                    comment: Some(text),
+                }),
+            },
+        }
+    }
+
+    if !diagnostics.is_empty() {
+        return Err(diagnostics);
+    }
+
+    indent_comments(&mut lines);
+    Ok(render(&lines))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::format_source;
+
+    fn format(source: &str) -> String {
+        format_source(source.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn formats_canonical_layout() {
+        let source = "\
+.ring acc = 0
+:start mkr 8 put acc 1 # first
+
+
+# the loop
+  :.loop
+out acc
+        jmp :.loop";
+        let expected = "\
+.ring acc = 0
+:start
+    mkr 8 put acc 1  # first
+
+# the loop
+:.loop
+    out acc
+    jmp :.loop
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let sources = [
+            ":start\nmkr 8 put 0 0b0011 # pad\n  put 0 0x0F   # hex\n\n\n:.loop # loop\njmp :.loop\n",
+            ".macro twice r\nout r out r\n.endm\n# greet\n.print 0 \"hi\\n\"\ntwice 0 # twice\n",
+            "mkr 1\n.data 0 \"a\\tb\"\nput 0 '\\''\nhlt 0\n",
+        ];
+        for source in sources {
+            let formatted = format(source);
+            assert_eq!(format(&formatted), formatted, "{}", source);
+        }
+    }
+
+    #[test]
+    fn aligns_comments_within_blocks() {
+        let source = "\
+mkr 1 # a
+put 0 0x41 # b
+out 0
+
+:end # c
+hlt 0    # d";
+        let expected = concat!(
+            "    mkr 1       # a\n",
+            "    put 0 0x41  # b\n",
+            "    out 0\n",
+            "\n",
+            ":end       # c\n",
+            "    hlt 0  # d\n",
+        );
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn keeps_numbers_as_written() {
+        let source =
+            "put 0 0b0011\nput 0 0x0F\nput 0 0xff\nput 0 017\nput 0 0\nput 0 42\nput 0 'a'\n";
+        let expected = concat!(
+            "    put 0 0b0011\n",
+            "    put 0 0x0F\n",
+            "    put 0 0xFF\n",
+            "    put 0 017\n",
+            "    put 0 0\n",
+            "    put 0 42\n",
+            "    put 0 'a'\n",
+        );
+        assert_eq!(format(source), expected);
+    }
+}
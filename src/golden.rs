@@ -0,0 +1,306 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    build::ProgramAssembler,
+    error::{Diagnostics, RingsError},
+    io::BufferIo,
+    render::DiagnosticRenderer,
+    vm::{ExitCode, Limits, RingsVM, EXIT_RUNTIME_ERROR},
+    Sources,
+};
+
+/// Number of bytes shown around the first difference
+const DIFF_CONTEXT: usize = 8;
+
+/// Output stream compared by a golden test
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Out,
+    Err,
+    Code,
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Out => write!(f, "out"),
+            Self::Err => write!(f, "err"),
+            Self::Code => write!(f, "code"),
+        }
+    }
+}
+
+/// Difference between the expected and actual contents of a stream
+#[derive(Debug)]
+pub struct Mismatch {
+    pub stream: Stream,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+impl Mismatch {
+    fn write_window(
+        f: &mut std::fmt::Formatter<'_>,
+        bytes: &[u8],
+        from: usize,
+        highlight: usize,
+    ) -> std::fmt::Result {
+        let to = bytes.len().min(highlight + DIFF_CONTEXT + 1);
+        if from > 0 {
+            write!(f, " ..")?;
+        }
+
+        for (i, byte) in bytes.iter().enumerate().take(to).skip(from) {
+            if i == highlight {
+                write!(f, " [{:02X}]", byte)?;
+            } else {
+                write!(f, " {:02X}", byte)?;
+            }
+        }
+
+        if highlight >= bytes.len() {
+            write!(f, " [EOF]")?;
+        } else if to < bytes.len() {
+            write!(f, " ..")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Stream::Code = self.stream {
+            return write!(
+                f,
+                "code: expected {}, got {}",
+                String::from_utf8_lossy(&self.expected),
+                String::from_utf8_lossy(&self.actual)
+            );
+        }
+
+        let first_difference = self
+            .expected
+            .iter()
+            .zip(self.actual.iter())
+            .position(|(e, a)| e != a)
+            .unwrap_or_else(|| self.expected.len().min(self.actual.len()));
+
+        writeln!(
+            f,
+            "{}: differs at byte {} (expected {} bytes, got {} bytes)",
+            self.stream,
+            first_difference,
+            self.expected.len(),
+            self.actual.len()
+        )?;
+
+        let from = first_difference.saturating_sub(DIFF_CONTEXT);
+        write!(f, "    expected:")?;
+        Self::write_window(f, &self.expected, from, first_difference)?;
+        write!(f, "\n    actual:  ")?;
+        Self::write_window(f, &self.actual, from, first_difference)
+    }
+}
+
+#[derive(Debug)]
+pub enum GoldenOutcome {
+    Passed,
+    Failed(Vec<Mismatch>),
+    /// The program or its expectation files could not be read, or the program could not be
+    /// assembled
+    Error(Diagnostics),
+}
+
+#[derive(Debug)]
+pub struct GoldenReport {
+    pub path: PathBuf,
+    /// Source files of the program, for rendering diagnostics
+    pub sources: Sources,
+    pub outcome: GoldenOutcome,
+}
+
+impl GoldenReport {
+    pub fn passed(&self) -> bool {
+        matches!(self.outcome, GoldenOutcome::Passed)
+    }
+}
+
+/// A `.rn` program with optional expectation files next to it: `.in` is fed as input,
+/// `.out` and `.err` are compared with the output and `.code` with the exit code.
+/// Missing expectation files are not checked.
+#[derive(Clone, Debug)]
+pub struct GoldenTest {
+    pub path: PathBuf,
+}
+
+impl GoldenTest {
+    /// Finds all `.rn` files in `dir` and its subdirectories, sorted by path
+    pub fn discover(dir: &Path) -> std::io::Result<Vec<Self>> {
+        let mut tests = Vec::new();
+        let mut dirs = vec![dir.to_owned()];
+
+        while let Some(dir) = dirs.pop() {
+            for entry in std::fs::read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                // Symbolic links to directories are not followed, they could form a cycle
+                if entry.file_type()?.is_dir() {
+                    dirs.push(path);
+                } else if path.extension().is_some_and(|e| e == "rn") {
+                    tests.push(Self { path });
+                }
+            }
+        }
+
+        tests.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(tests)
+    }
+
+    fn expectation(&self, extension: &str) -> std::io::Result<Option<Vec<u8>>> {
+        match std::fs::read(self.path.with_extension(extension)) {
+            Ok(v) => Ok(Some(v)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Runs the program like `rings run` does, returning the exit code, output and error output.
+    /// A runtime error is rendered to the error output and exits with [`EXIT_RUNTIME_ERROR`].
+    /// The sources with their paths relative to the directory of the test, so that the error
+    /// output does not depend on how the test directory was spelled on the command line
+    fn relative_sources(&self, sources: &Sources) -> Sources {
+        let dir = self.path.parent().unwrap_or_else(|| Path::new(""));
+        let mut relative = Sources::new();
+        for (_, file) in sources.iter() {
+            let path = file.path.strip_prefix(dir).unwrap_or(&file.path);
+            relative.add(path.to_owned(), file.text.clone());
+        }
+        relative
+    }
+
+    fn execute(
+        &self,
+        source: &[u8],
+        sources: &mut Sources,
+        input: Vec<u8>,
+        limits: Limits,
+    ) -> Result<(ExitCode, Vec<u8>, Vec<u8>), Diagnostics> {
+        let program = ProgramAssembler::assemble_file(&self.path, source, &[], true, sources)?;
+        let mut vm = RingsVM::new(program);
+        vm.limits = limits;
+
+        let mut io = BufferIo::new(input);
+        let result = vm.run(&mut io);
+        let (out, mut err) = io.into_output();
+        let exit_code = match result {
+            Ok(exit_code) => exit_code,
+            Err(error) => {
+                DiagnosticRenderer::with_sources(&self.relative_sources(sources), false)
+                    .render(&vm.diagnostics(error), &mut err)
+                    .map_err(RingsError::from)?;
+                EXIT_RUNTIME_ERROR
+            }
+        };
+
+        Ok((exit_code, out, err))
+    }
+
+    pub fn run(&self, limits: Limits) -> GoldenReport {
+        let mut report = GoldenReport {
+            path: self.path.clone(),
+            sources: Sources::new(),
+            outcome: GoldenOutcome::Passed,
+        };
+
+        let read = |extension| {
+            self.expectation(extension)
+                .map_err(|e| Diagnostics::from(RingsError::from(e)))
+        };
+        let files = std::fs::read(&self.path)
+            .map_err(|e| Diagnostics::from(RingsError::from(e)))
+            .and_then(|source| {
+                Ok((
+                    source,
+                    read("in")?,
+                    read("out")?,
+                    read("err")?,
+                    read("code")?,
+                ))
+            });
+        let (source, input, out, err, code) = match files {
+            Ok(v) => v,
+            Err(e) => {
+                report.outcome = GoldenOutcome::Error(e);
+                return report;
+            }
+        };
+
+        let input = input.unwrap_or_default();
+        let (exit_code, actual_out, actual_err) =
+            match self.execute(&source, &mut report.sources, input, limits) {
+                Ok(v) => v,
+                Err(e) => {
+                    report.outcome = GoldenOutcome::Error(e);
+                    return report;
+                }
+            };
+
+        let mut mismatches = Vec::new();
+        let mut compare = |stream, expected: Option<Vec<u8>>, actual: Vec<u8>| {
+            if let Some(expected) = expected {
+                if expected != actual {
+                    mismatches.push(Mismatch {
+                        stream,
+                        expected,
+                        actual,
+                    });
+                }
+            }
+        };
+
+        compare(Stream::Out, out, actual_out);
+        compare(Stream::Err, err, actual_err);
+        // Exit codes are compared as text, ignoring surrounding whitespace
+        let code = code.map(|c| String::from_utf8_lossy(&c).trim().as_bytes().to_vec());
+        compare(Stream::Code, code, exit_code.to_string().into_bytes());
+
+        if !mismatches.is_empty() {
+            report.outcome = GoldenOutcome::Failed(mismatches);
+        }
+
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::GoldenTest;
+    use crate::vm::Limits;
+
+    #[test]
+    fn error_output_does_not_depend_on_path_spelling() {
+        let dir = std::env::temp_dir().join(format!("rings-golden-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        std::fs::write(dir.join("sub/fail.rn"), "mkr 1\n.include \"put.inc\"\n").unwrap();
+        std::fs::write(dir.join("sub/put.inc"), "put 1 3\n").unwrap();
+        std::fs::write(
+            dir.join("sub/fail.err"),
+            "error: Invalid ring 1\n --> put.inc:1:1\n  |\n1 | put 1 3\n  | ^^^^^^^\n\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("sub/fail.code"), "70").unwrap();
+
+        for spelling in [dir.clone(), dir.join("./sub/../"), dir.join("sub/.")] {
+            let tests = GoldenTest::discover(&spelling).unwrap();
+            assert_eq!(tests.len(), 1);
+            let report = tests[0].run(Limits::default());
+            assert!(report.passed(), "{:?}: {:?}", spelling, report.outcome);
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
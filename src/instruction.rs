@@ -1,4 +1,7 @@
-use crate::{io::RingsIo, vm::{Ring, RingId, RingsVM, RuntimeResult}};
+use crate::{
+    io::RingsIo,
+    vm::{DivisionByZeroPolicy, EofPolicy, RingId, RingsVM, RuntimeError, RuntimeResult},
+};
 
 pub type Label = usize;
 pub type Literal = u8;
@@ -28,6 +31,7 @@ pub enum InstructionPrimitive {
     ROT,
     SWP,
     INP,
+    INF,
     OUT,
     ERR,
     ADD,
@@ -49,6 +53,7 @@ impl InstructionPrimitive {
             Self::ROT => 2,
             Self::SWP => 2,
             Self::INP => 1,
+            Self::INF => 2,
             Self::OUT => 1,
             Self::ERR => 1,
             Self::ADD => 3,
@@ -64,6 +69,32 @@ impl InstructionPrimitive {
     }
 }
 
+impl std::fmt::Display for InstructionPrimitive {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::MKR => "mkr",
+            Self::PUT => "put",
+            Self::ROT => "rot",
+            Self::SWP => "swp",
+            Self::INP => "inp",
+            Self::INF => "inf",
+            Self::OUT => "out",
+            Self::ERR => "err",
+            Self::ADD => "add",
+            Self::SUB => "sub",
+            Self::MUL => "mul",
+            Self::DIV => "div",
+            Self::JMP => "jmp",
+            Self::JEQ => "jeq",
+            Self::JGT => "jgt",
+            Self::JLT => "jlt",
+            Self::HLT => "hlt",
+        };
+
+        f.write_str(name)
+    }
+}
+
 impl TryFrom<(char, char, char)> for InstructionPrimitive {
     type Error = InstructionError;
     fn try_from(value: (char, char, char)) -> Result<Self, Self::Error> {
@@ -77,6 +108,7 @@ impl TryFrom<(char, char, char)> for InstructionPrimitive {
             ('R', 'O', 'T') => Ok(Self::ROT),
             ('S', 'W', 'P') => Ok(Self::SWP),
             ('I', 'N', 'P') => Ok(Self::INP),
+            ('I', 'N', 'F') => Ok(Self::INF),
             ('O', 'U', 'T') => Ok(Self::OUT),
             ('E', 'R', 'R') => Ok(Self::ERR),
             ('A', 'D', 'D') => Ok(Self::ADD),
@@ -100,6 +132,9 @@ pub enum Instruction {
     ROT(RingId, Literal),
     SWP(RingId, RingId),
     INP(RingId),
+    /// Like INP, but sets the second ring to 1 on end of input and to 0 otherwise.
+    /// On end of input the first ring is left unchanged, regardless of the [`EofPolicy`].
+    INF(RingId, RingId),
     OUT(RingId),
     ERR(RingId),
     ADD(RingId, RingId, RingId),
@@ -114,6 +149,87 @@ pub enum Instruction {
 }
 
 impl Instruction {
+    pub fn primitive(&self) -> InstructionPrimitive {
+        match self {
+            Self::MKR(..) => InstructionPrimitive::MKR,
+            Self::PUT(..) => InstructionPrimitive::PUT,
+            Self::ROT(..) => InstructionPrimitive::ROT,
+            Self::SWP(..) => InstructionPrimitive::SWP,
+            Self::INP(..) => InstructionPrimitive::INP,
+            Self::INF(..) => InstructionPrimitive::INF,
+            Self::OUT(..) => InstructionPrimitive::OUT,
+            Self::ERR(..) => InstructionPrimitive::ERR,
+            Self::ADD(..) => InstructionPrimitive::ADD,
+            Self::SUB(..) => InstructionPrimitive::SUB,
+            Self::MUL(..) => InstructionPrimitive::MUL,
+            Self::DIV(..) => InstructionPrimitive::DIV,
+            Self::JMP(..) => InstructionPrimitive::JMP,
+            Self::JEQ(..) => InstructionPrimitive::JEQ,
+            Self::JGT(..) => InstructionPrimitive::JGT,
+            Self::JLT(..) => InstructionPrimitive::JLT,
+            Self::HLT(..) => InstructionPrimitive::HLT,
+        }
+    }
+
+    /// Rings referenced by the instruction's arguments, without duplicates
+    pub fn rings(&self) -> Vec<RingId> {
+        let mut rings = match self {
+            Self::MKR(..) | Self::JMP(..) | Self::HLT(..) => vec![],
+            Self::PUT(r, _) | Self::ROT(r, _) | Self::INP(r) | Self::OUT(r) | Self::ERR(r) => {
+                vec![*r]
+            }
+            Self::SWP(a, b)
+            | Self::INF(a, b)
+            | Self::JEQ(a, b, _)
+            | Self::JGT(a, b, _)
+            | Self::JLT(a, b, _) => {
+                vec![*a, *b]
+            }
+            Self::ADD(a, b, c) | Self::SUB(a, b, c) | Self::MUL(a, b, c) | Self::DIV(a, b, c) => {
+                vec![*a, *b, *c]
+            }
+        };
+
+        rings.sort_unstable();
+        rings.dedup();
+        rings
+    }
+
+    /// Jump target of JMP, JEQ, JGT and JLT
+    pub fn target(&self) -> Option<Label> {
+        match self {
+            Self::JMP(tgt) | Self::JEQ(_, _, tgt) | Self::JGT(_, _, tgt) | Self::JLT(_, _, tgt) => {
+                Some(*tgt)
+            }
+            _ => None,
+        }
+    }
+
+    /// Writes the instruction in canonical lowercase syntax, naming jump targets with `label`
+    pub fn write_with_labels<W, L, D>(&self, f: &mut W, label: L) -> std::fmt::Result
+    where
+        W: std::fmt::Write,
+        L: FnOnce(Label) -> D,
+        D: std::fmt::Display,
+    {
+        write!(f, "{}", self.primitive())?;
+        match self {
+            Self::MKR(a) | Self::INP(a) | Self::OUT(a) | Self::ERR(a) | Self::HLT(a) => {
+                write!(f, " {}", a)
+            }
+            Self::PUT(a, b) | Self::ROT(a, b) | Self::SWP(a, b) | Self::INF(a, b) => {
+                write!(f, " {} {}", a, b)
+            }
+            Self::ADD(a, b, c) | Self::SUB(a, b, c) | Self::MUL(a, b, c) | Self::DIV(a, b, c) => {
+                write!(f, " {} {} {}", a, b, c)
+            }
+            Self::JMP(tgt) => write!(f, " :{}", label(*tgt)),
+            Self::JEQ(a, b, tgt) | Self::JGT(a, b, tgt) | Self::JLT(a, b, tgt) => {
+                write!(f, " {} {} :{}", a, b, label(*tgt))
+            }
+        }
+    }
+
     pub fn validate(&self) -> InstructionResult<()> {
         match self {
             Self::MKR(0) => Err(InstructionError::ZeroRingLength),
@@ -145,7 +261,7 @@ impl Instruction {
         }
 
         match self {
-            Self::MKR(capacity) => vm.rings.push(Ring::new(*capacity)?),
+            Self::MKR(capacity) => vm.make_ring(*capacity)?,
             Self::PUT(ring, val) => *vm.get_ring(*ring)?.current_mut() = *val,
             Self::ROT(ring, by) => vm.get_ring(*ring)?.rotate(*by),
             Self::SWP(a, b) => {
@@ -159,13 +275,51 @@ impl Instruction {
 
                 *(vm.get_ring(*a)?.current_mut()) = val_b;
             }
-            Self::INP(ring) => *vm.get_ring(*ring)?.current_mut() = io.inp(vm),
+            Self::INP(ring) => {
+                // Check the ring before consuming input
+                let current = *vm.get_ring(*ring)?.current();
+                let val = match (io.inp(vm), vm.end_of_input) {
+                    (Some(val), _) => val,
+                    (None, EofPolicy::Sentinel(val)) => val,
+                    (None, EofPolicy::Unchanged) => current,
+                    (None, EofPolicy::Error) => return Err(RuntimeError::EndOfInput(*ring)),
+                };
+                *vm.get_ring(*ring)?.current_mut() = val;
+            }
+            Self::INF(ring, flag) => {
+                // Check both rings before consuming input
+                vm.get_ring(*ring)?;
+                vm.get_ring(*flag)?;
+
+                let eof = match io.inp(vm) {
+                    Some(val) => {
+                        *vm.get_ring(*ring)?.current_mut() = val;
+                        0
+                    }
+                    None => 1,
+                };
+                *vm.get_ring(*flag)?.current_mut() = eof;
+            }
             Self::OUT(ring) => io.out(*vm.get_ring(*ring)?.current(), vm),
             Self::ERR(ring) => io.err(*vm.get_ring(*ring)?.current(), vm),
             Self::ADD(a, b, c) => arith!(a, b, c, wrapping_add),
             Self::SUB(a, b, c) => arith!(a, b, c, wrapping_sub),
             Self::MUL(a, b, c) => arith!(a, b, c, wrapping_mul),
-            Self::DIV(a, b, c) => arith!(a, b, c, wrapping_div),
+            Self::DIV(a, b, c) => {
+                let dividend = *vm.get_ring(*a)?.current();
+                let divisor = *vm.get_ring(*b)?.current();
+                let val = match (divisor, vm.division_by_zero) {
+                    (0, DivisionByZeroPolicy::Error) => {
+                        return Err(RuntimeError::DivisionByZero {
+                            dividend: *a,
+                            divisor: *b,
+                        })
+                    }
+                    (0, DivisionByZeroPolicy::Define(val)) => val,
+                    (divisor, _) => dividend / divisor,
+                };
+                *vm.get_ring(*c)?.current_mut() = val;
+            }
             Self::JMP(tgt) => jumpif!(tgt),
             Self::JEQ(a, b, tgt) => jumpif!(tgt, a == b),
             Self::JGT(a, b, tgt) => jumpif!(tgt, a > b),
@@ -176,3 +330,10 @@ impl Instruction {
         Ok(())
     }
 }
+
+/// Canonical lowercase syntax. Jump targets are rendered as generated labels `:L<address>`.
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.write_with_labels(f, |tgt| format!("L{}", tgt))
+    }
+}
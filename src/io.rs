@@ -1,27 +1,279 @@
+use std::io::{BufWriter, Stderr, Stdout, Write};
+
 use byteorder::{ReadBytesExt, WriteBytesExt};
 
 use crate::vm::RingsVM;
 
 pub trait RingsIo {
-    fn inp(&mut self, vm: &RingsVM) -> u8;
+    /// Reads a byte of input, `None` once the input is exhausted.
+    /// What INP does then is decided by the VM's [`EofPolicy`](crate::vm::EofPolicy).
+    fn inp(&mut self, vm: &RingsVM) -> Option<u8>;
 
     fn out(&mut self, value: u8, vm: &RingsVM);
 
     fn err(&mut self, value: u8, vm: &RingsVM);
+
+    /// Writes out any buffered output. Called by the VM when it halts or fails.
+    fn flush(&mut self) {}
+}
+
+/// Standard input and output. `out` and `err` are buffered; the buffers are flushed when the VM
+/// halts or fails, before every `inp` so that prompts appear before the program waits for input,
+/// and when the value is dropped. Switching between `out` and `err` flushes the other stream,
+/// keeping the order of the two streams when they go to the same terminal.
+pub struct SystemStdio {
+    stdout: BufWriter<Stdout>,
+    stderr: BufWriter<Stderr>,
+}
+
+impl SystemStdio {
+    pub fn new() -> Self {
+        Self {
+            stdout: BufWriter::new(std::io::stdout()),
+            stderr: BufWriter::new(std::io::stderr()),
+        }
+    }
 }
 
-pub struct SystemStdio;
+impl Default for SystemStdio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl RingsIo for SystemStdio {
     fn out(&mut self, value: u8, _vm: &RingsVM) {
-        let _ = std::io::stdout().write_u8(value);
+        if !self.stderr.buffer().is_empty() {
+            let _ = self.stderr.flush();
+        }
+
+        let _ = self.stdout.write_u8(value);
     }
 
-    fn inp(&mut self, _vm: &RingsVM) -> u8 {
-        std::io::stdin().read_u8().unwrap_or(0xFF)
+    fn inp(&mut self, _vm: &RingsVM) -> Option<u8> {
+        self.flush();
+        std::io::stdin().read_u8().ok()
+    }
+
+    fn err(&mut self, value: u8, _vm: &RingsVM) {
+        if !self.stdout.buffer().is_empty() {
+            let _ = self.stdout.flush();
+        }
+
+        let _ = self.stderr.write_u8(value);
+    }
+
+    fn flush(&mut self) {
+        let _ = self.stdout.flush();
+        let _ = self.stderr.flush();
+    }
+}
+
+impl Drop for SystemStdio {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+/// In-memory IO. Reads input from a buffer and captures `out` and `err` into separate buffers.
+///
+/// ```
+/// use rings::{build::ProgramAssembler, io::BufferIo, vm::RingsVM};
+///
+/// let program = ProgramAssembler::assemble(&b"mkr 1 inp 0 out 0 err 0"[..], false).unwrap();
+/// let mut io = BufferIo::new(b"A");
+/// RingsVM::execute(&program, &mut io).unwrap();
+///
+/// assert_eq!(io.output(), b"A");
+/// assert_eq!(io.error_output(), b"A");
+/// ```
+#[derive(Clone, Default, Debug)]
+pub struct BufferIo {
+    input: Vec<u8>,
+    position: usize,
+    out: Vec<u8>,
+    err: Vec<u8>,
+}
+
+impl BufferIo {
+    pub fn new<I>(input: I) -> Self
+    where
+        I: Into<Vec<u8>>,
+    {
+        Self {
+            input: input.into(),
+            ..Self::default()
+        }
+    }
+
+    /// Input that has not been read yet
+    pub fn remaining_input(&self) -> &[u8] {
+        &self.input[self.position..]
+    }
+
+    /// Bytes written by OUT
+    pub fn output(&self) -> &[u8] {
+        &self.out
+    }
+
+    /// Bytes written by ERR
+    pub fn error_output(&self) -> &[u8] {
+        &self.err
+    }
+
+    /// Returns the captured `(out, err)` buffers
+    pub fn into_output(self) -> (Vec<u8>, Vec<u8>) {
+        (self.out, self.err)
+    }
+}
+
+impl RingsIo for BufferIo {
+    fn inp(&mut self, _vm: &RingsVM) -> Option<u8> {
+        let value = self.input.get(self.position).copied();
+        if value.is_some() {
+            self.position += 1;
+        }
+
+        value
+    }
+
+    fn out(&mut self, value: u8, _vm: &RingsVM) {
+        self.out.push(value);
     }
 
     fn err(&mut self, value: u8, _vm: &RingsVM) {
-        let _ = std::io::stderr().write_u8(value);
+        self.err.push(value);
+    }
+}
+
+/// IO backed by closures, one per instruction
+pub struct CallbackIo<I, O, E> {
+    inp: I,
+    out: O,
+    err: E,
+}
+
+impl<I, O, E> CallbackIo<I, O, E>
+where
+    I: FnMut(&RingsVM) -> Option<u8>,
+    O: FnMut(u8, &RingsVM),
+    E: FnMut(u8, &RingsVM),
+{
+    pub fn new(inp: I, out: O, err: E) -> Self {
+        Self { inp, out, err }
+    }
+}
+
+impl<I, O, E> RingsIo for CallbackIo<I, O, E>
+where
+    I: FnMut(&RingsVM) -> Option<u8>,
+    O: FnMut(u8, &RingsVM),
+    E: FnMut(u8, &RingsVM),
+{
+    fn inp(&mut self, vm: &RingsVM) -> Option<u8> {
+        (self.inp)(vm)
+    }
+
+    fn out(&mut self, value: u8, vm: &RingsVM) {
+        (self.out)(value, vm)
+    }
+
+    fn err(&mut self, value: u8, vm: &RingsVM) {
+        (self.err)(value, vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BufferIo;
+    use crate::{
+        build::ProgramAssembler,
+        error::RingsError,
+        vm::{EofPolicy, ExitCode, RingsVM, RuntimeError},
+    };
+
+    fn run(source: &str, input: &[u8], eof: EofPolicy) -> (Result<ExitCode, RingsError>, BufferIo) {
+        let program = ProgramAssembler::assemble(source.as_bytes(), false).unwrap();
+        let mut vm = RingsVM::new(program);
+        vm.end_of_input = eof;
+
+        let mut io = BufferIo::new(input);
+        let result = vm.run(&mut io).map_err(|e| e.unwrap());
+        (result, io)
+    }
+
+    #[test]
+    fn captures_out_and_err_separately() {
+        let source = "mkr 1 put 0 'o' out 0 put 0 'e' err 0 put 0 'k' out 0";
+        let (result, io) = run(source, b"", EofPolicy::default());
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(io.output(), b"ok");
+        assert_eq!(io.error_output(), b"e");
+    }
+
+    #[test]
+    fn reads_input_in_order() {
+        let source = "mkr 1 inp 0 out 0 inp 0 err 0";
+        let (result, io) = run(source, b"abc", EofPolicy::default());
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(io.output(), b"a");
+        assert_eq!(io.error_output(), b"b");
+        assert_eq!(io.remaining_input(), b"c");
+    }
+
+    #[test]
+    fn end_of_input_stores_sentinel() {
+        let source = "mkr 1 inp 0 out 0 inp 0 out 0";
+        let (_, io) = run(source, b"a", EofPolicy::default());
+        assert_eq!(io.output(), b"a\xFF");
+
+        let (_, io) = run(source, b"a", EofPolicy::Sentinel(0));
+        assert_eq!(io.output(), b"a\0");
+    }
+
+    #[test]
+    fn end_of_input_leaves_ring_unchanged() {
+        let source = "mkr 1 put 0 'x' inp 0 out 0";
+        let (result, io) = run(source, b"", EofPolicy::Unchanged);
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(io.output(), b"x");
+    }
+
+    #[test]
+    fn end_of_input_fails() {
+        let source = "mkr 1 out 0 inp 0 out 0";
+        let (result, io) = run(source, b"", EofPolicy::Error);
+
+        assert!(matches!(
+            result,
+            Err(RingsError::Runtime(RuntimeError::EndOfInput(0)))
+        ));
+        assert_eq!(io.output(), b"\0");
+    }
+
+    #[test]
+    fn inf_sets_flag_at_end_of_input() {
+        let source = "mkr 1 mkr 1 inf 0 1 out 0 err 1 inf 0 1 out 0 err 1";
+        let (result, io) = run(source, b"a", EofPolicy::Error);
+
+        assert_eq!(result.unwrap(), 0);
+        assert_eq!(io.output(), b"aa");
+        assert_eq!(io.error_output(), b"\0\x01");
+    }
+
+    #[test]
+    fn invalid_ring_does_not_consume_input() {
+        for source in ["mkr 1 inp 1", "mkr 1 inf 1 0", "mkr 1 inf 0 1"] {
+            let (result, io) = run(source, b"ab", EofPolicy::default());
+
+            assert!(matches!(
+                result,
+                Err(RingsError::Runtime(RuntimeError::InvalidRing(1)))
+            ));
+            assert_eq!(io.remaining_input(), b"ab");
+        }
     }
 }
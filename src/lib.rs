@@ -1,14 +1,25 @@
-#![feature(try_trait_v2)]
+#![cfg_attr(feature = "nightly", feature(try_trait_v2, try_trait_v2_residual))]
 
+#[cfg(feature = "nightly")]
 use std::{
     convert::Infallible,
+    ops::{ControlFlow, FromResidual, Residual, Try},
+};
+use std::{
     fmt::{Debug, Display},
-    ops::{ControlFlow, Deref, DerefMut, FromResidual, Try},
+    ops::{Deref, DerefMut},
+    path::PathBuf,
 };
 
 pub mod build;
+pub mod debugger;
 pub mod error;
+pub mod format;
+pub mod golden;
 pub mod instruction;
+pub mod lint;
+pub mod render;
+pub mod trace;
 pub mod vm;
 pub mod io;
 
@@ -20,6 +31,8 @@ pub enum NumberSystem {
     Octal,
     Binary,
     Hexadecimal,
+    /// A character literal, e.g. `'A'`
+    Character,
 }
 
 impl std::fmt::Display for NumberSystem {
@@ -30,77 +43,167 @@ impl std::fmt::Display for NumberSystem {
             Self::Binary => write!(f, "Bin"),
             Self::Hexadecimal => write!(f, "Hex"),
             Self::Octal => write!(f, "Oct"),
+            Self::Character => write!(f, "Char"),
+        }
+    }
+}
+
+/// Identifies the source file a location refers to
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceId(pub usize);
+
+/// A source file of a program
+#[derive(Clone, Debug)]
+pub struct SourceFile {
+    pub path: PathBuf,
+    pub text: String,
+}
+
+/// The source files of a program, indexed by [`SourceId`]: the main file first,
+/// followed by the files it includes
+#[derive(Clone, Debug, Default)]
+pub struct Sources {
+    files: Vec<SourceFile>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, path: PathBuf, text: String) -> SourceId {
+        self.files.push(SourceFile { path, text });
+        SourceId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, id: SourceId) -> Option<&SourceFile> {
+        self.files.get(id.0)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (SourceId, &SourceFile)> {
+        self.files
+            .iter()
+            .enumerate()
+            .map(|(i, file)| (SourceId(i), file))
+    }
+}
+
+/// A point in the source, between two characters
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Position {
+    pub line_number: usize,
+    pub char_number: usize,
+    /// Byte offset from the start of the source
+    pub offset: usize,
+}
+
+impl Default for Position {
+    fn default() -> Self {
+        Self {
+            line_number: 1,
+            char_number: 1,
+            offset: 0,
+        }
+    }
+}
+
+impl Position {
+    /// Moves the position past `c`
+    pub fn advance(&mut self, c: char) {
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line_number += 1;
+            self.char_number = 1;
+        } else {
+            self.char_number += 1;
         }
     }
 }
 
 pub type LocalizedResult<T, E> = Localized<Result<T, E>>;
+/// A value together with the span of source it was read from. `line_number`, `char_number`
+/// and `offset` mark the start of the span, `end` the position just past its last character.
 pub struct Localized<V> {
     pub line_number: usize,
     pub char_number: usize,
+    pub offset: usize,
+    pub end: Position,
+    pub source: SourceId,
     pub value: V,
 }
 
 impl<V> Localized<V> {
     pub fn new(value: V) -> Self {
+        Self::at(Position::default(), SourceId::default(), value)
+    }
+
+    /// An empty span at `position`
+    pub fn at(position: Position, source: SourceId, value: V) -> Self {
         Self {
-            line_number: 1,
-            char_number: 1,
+            line_number: position.line_number,
+            char_number: position.char_number,
+            offset: position.offset,
+            end: position,
+            source,
             value,
         }
     }
 
+    pub fn start(&self) -> Position {
+        Position {
+            line_number: self.line_number,
+            char_number: self.char_number,
+            offset: self.offset,
+        }
+    }
+
+    /// Length of the span in bytes
+    pub fn len(&self) -> usize {
+        self.end.offset.saturating_sub(self.offset)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The same value, with the span extended or shortened to end at `end`
+    pub fn with_end(mut self, end: Position) -> Self {
+        self.end = end;
+        self
+    }
+
     pub fn transform<N>(&self, new: N) -> Localized<N> {
         Localized {
-            char_number: self.char_number,
             line_number: self.line_number,
+            char_number: self.char_number,
+            offset: self.offset,
+            end: self.end,
+            source: self.source,
             value: new,
         }
     }
 
     pub fn cut(self) -> (Localized<()>, V) {
-        let Self {
-            line_number,
-            char_number,
-            value,
-        } = self;
-
-        (
-            Localized {
-                char_number,
-                line_number,
-                value: (),
-            },
-            value,
-        )
+        let location = self.transform(());
+        (location, self.value)
     }
 
-    pub fn inc_char(&mut self) {
-        self.char_number += 1;
-    }
-
-    pub fn new_line(&mut self) {
-        self.line_number += 1;
-        self.char_number = 1;
+    /// Moves the start of the span past `c`, leaving an empty span
+    pub fn advance(&mut self, c: char) {
+        let mut position = self.start();
+        position.advance(c);
+        self.line_number = position.line_number;
+        self.char_number = position.char_number;
+        self.offset = position.offset;
+        self.end = position;
     }
 
     pub fn map<F, N>(self, map: F) -> Localized<N>
     where
         F: FnOnce(V) -> N,
     {
-        let Self {
-            line_number,
-            char_number,
-            value,
-        } = self;
-
-        let value = (map)(value);
-
-        Localized {
-            line_number,
-            char_number,
-            value,
-        }
+        let (location, value) = self.cut();
+        location.transform((map)(value))
     }
 
     pub fn unwrap(self) -> V {
@@ -109,36 +212,21 @@ impl<V> Localized<V> {
 }
 
 impl<T, E> Localized<Result<T, E>> {
-    pub fn into_err(self) -> Option<Localized<E>> {
-        let Self {
-            line_number,
-            char_number,
-            value,
-        } = self;
+    /// Moves the location into both the success and the error value
+    pub fn transpose(self) -> Result<Localized<T>, Localized<E>> {
+        let (location, value) = self.cut();
         match value {
-            Ok(..) => None,
-            Err(e) => Some(Localized {
-                line_number,
-                char_number,
-                value: e,
-            }),
+            Ok(value) => Ok(location.transform(value)),
+            Err(value) => Err(location.transform(value)),
         }
     }
 
+    pub fn into_err(self) -> Option<Localized<E>> {
+        self.transpose().err()
+    }
+
     pub fn into_ok(self) -> Option<Localized<T>> {
-        let Self {
-            line_number,
-            char_number,
-            value,
-        } = self;
-        match value {
-            Err(..) => None,
-            Ok(v) => Some(Localized {
-                line_number,
-                char_number,
-                value: v,
-            }),
-        }
+        self.transpose().ok()
     }
 }
 
@@ -156,35 +244,18 @@ where
     V: Clone,
 {
     fn clone(&self) -> Self {
-        Self {
-            value: self.value.clone(),
-            char_number: self.char_number,
-            line_number: self.line_number,
-        }
+        self.transform(self.value.clone())
     }
 }
 
+#[cfg(feature = "nightly")]
 impl<T, E> Try for Localized<Result<T, E>> {
     type Output = Localized<T>;
     type Residual = Localized<Result<Infallible, E>>;
     fn branch(self) -> ControlFlow<Self::Residual, Self::Output> {
-        let Self {
-            line_number,
-            char_number,
-            value,
-        } = self;
-
-        match value {
-            Ok(value) => ControlFlow::Continue(Localized {
-                value,
-                line_number,
-                char_number,
-            }),
-            Err(value) => ControlFlow::Break(Localized {
-                value: Err(value),
-                line_number,
-                char_number,
-            }),
+        match self.transpose() {
+            Ok(value) => ControlFlow::Continue(value),
+            Err(value) => ControlFlow::Break(value.map(Err)),
         }
     }
 
@@ -193,12 +264,18 @@ impl<T, E> Try for Localized<Result<T, E>> {
     }
 }
 
+#[cfg(feature = "nightly")]
 impl<T, E> FromResidual<Localized<Result<Infallible, E>>> for Localized<Result<T, E>> {
     fn from_residual(residual: Localized<Result<Infallible, E>>) -> Self {
         residual.map(Result::from_residual)
     }
 }
 
+#[cfg(feature = "nightly")]
+impl<T, E> Residual<Localized<T>> for Localized<Result<Infallible, E>> {
+    type TryType = Localized<Result<T, E>>;
+}
+
 impl<V> Deref for Localized<V> {
     type Target = V;
     fn deref(&self) -> &Self::Target {
@@ -286,6 +363,19 @@ impl<V> MaybeLocalized<V> {
 }
 
 impl<T, E> MaybeLocalized<Result<T, E>> {
+    /// Moves the location into both the success and the error value
+    pub fn transpose(self) -> Result<MaybeLocalized<T>, MaybeLocalized<E>> {
+        match self {
+            Self::General(v) => v
+                .map(MaybeLocalized::General)
+                .map_err(MaybeLocalized::General),
+            Self::Localized(v) => v
+                .transpose()
+                .map(MaybeLocalized::Localized)
+                .map_err(MaybeLocalized::Localized),
+        }
+    }
+
     pub fn into_err(self) -> Option<MaybeLocalized<E>> {
         match self {
             Self::General(v) => match v {
@@ -331,6 +421,7 @@ impl<V> From<V> for MaybeLocalized<V> {
     }
 }
 
+#[cfg(feature = "nightly")]
 impl<T, E> Try for MaybeLocalized<Result<T, E>> {
     type Output = MaybeLocalized<T>;
     type Residual = MaybeLocalized<Result<Infallible, E>>;
@@ -356,6 +447,12 @@ impl<T, E> Try for MaybeLocalized<Result<T, E>> {
     }
 }
 
+#[cfg(feature = "nightly")]
+impl<T, E> Residual<MaybeLocalized<T>> for MaybeLocalized<Result<Infallible, E>> {
+    type TryType = MaybeLocalized<Result<T, E>>;
+}
+
+#[cfg(feature = "nightly")]
 impl<T, E> FromResidual<MaybeLocalized<Result<Infallible, E>>> for MaybeLocalized<Result<T, E>> {
     fn from_residual(residual: MaybeLocalized<Result<Infallible, E>>) -> Self {
         match residual {
@@ -365,12 +462,14 @@ impl<T, E> FromResidual<MaybeLocalized<Result<Infallible, E>>> for MaybeLocalize
     }
 }
 
+#[cfg(feature = "nightly")]
 impl<T, E> FromResidual<Localized<Result<Infallible, E>>> for MaybeLocalized<Result<T, E>> {
     fn from_residual(residual: Localized<Result<Infallible, E>>) -> Self {
         Self::Localized(Localized::from_residual(residual))
     }
 }
 
+#[cfg(feature = "nightly")]
 impl<T, E> FromResidual<Result<Infallible, E>> for MaybeLocalized<Result<T, E>> {
     fn from_residual(residual: Result<Infallible, E>) -> Self {
         Self::General(Result::from_residual(residual))
@@ -0,0 +1,591 @@
+//! Checks for likely mistakes in programs that assemble fine, see `rings check`.
+//!
+//! Each [`Warning`] can be allowed with an `# allow(<lint>)` comment on the line it points at,
+//! or on its own line right before it. Several lints are separated by commas.
+
+use std::collections::HashMap;
+
+use crate::{
+    instruction::{Instruction, Label},
+    vm::RingId,
+    Localized, Sources,
+};
+
+/// The kinds of warnings, named as in `# allow(...)` comments
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Lint {
+    UnusedLabel,
+    Unreachable,
+    UncreatedRing,
+    DivisionByZero,
+    LabelAtEnd,
+}
+
+impl Lint {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::UnusedLabel => "unused-label",
+            Self::Unreachable => "unreachable",
+            Self::UncreatedRing => "uncreated-ring",
+            Self::DivisionByZero => "division-by-zero",
+            Self::LabelAtEnd => "label-at-end",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Warning {
+    /// A label that is never jumped to
+    UnusedLabel(String),
+    /// The first of instructions that no path from the start of the program leads to
+    Unreachable,
+    /// A ring used where at most `rings` rings can have been created by `mkr`
+    UncreatedRing { ring: RingId, rings: usize },
+    /// `div` with a divisor ring that is 0 on every path leading to it
+    DivisionByZero(RingId),
+    /// A label after the last instruction, jumping to it ends the program
+    LabelAtEnd(String),
+}
+
+impl Warning {
+    pub fn lint(&self) -> Lint {
+        match self {
+            Self::UnusedLabel(..) => Lint::UnusedLabel,
+            Self::Unreachable => Lint::Unreachable,
+            Self::UncreatedRing { .. } => Lint::UncreatedRing,
+            Self::DivisionByZero(..) => Lint::DivisionByZero,
+            Self::LabelAtEnd(..) => Lint::LabelAtEnd,
+        }
+    }
+
+    pub fn hint(&self) -> Option<String> {
+        match self {
+            Self::LabelAtEnd(..) => Some("use hlt to end the program explicitly".to_owned()),
+            _ => None,
+        }
+    }
+}
+
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::UnusedLabel(label) => write!(f, "Unused label: {}", label),
+            Self::Unreachable => write!(f, "Unreachable instruction"),
+            Self::UncreatedRing { ring, rings: 0 } => {
+                write!(f, "Ring {} is used before any ring is created", ring)
+            }
+            Self::UncreatedRing { ring, rings: 1 } => {
+                write!(
+                    f,
+                    "Ring {} is used where at most 1 ring has been created",
+                    ring
+                )
+            }
+            Self::UncreatedRing { ring, rings } => write!(
+                f,
+                "Ring {} is used where at most {} rings have been created",
+                ring, rings
+            ),
+            Self::DivisionByZero(ring) => {
+                write!(f, "Division by zero, ring {} is always 0 here", ring)
+            }
+            Self::LabelAtEnd(label) => write!(f, "Label at the end of the program: {}", label),
+        }
+    }
+}
+
+/// A label of the checked program
+#[derive(Clone, Debug)]
+pub struct DefinedLabel {
+    /// The name as written in the source
+    pub name: String,
+    pub address: Label,
+    /// Jumped to, or the scope of local labels
+    pub used: bool,
+}
+
+/// Addresses execution can continue at after the instruction at `address`.
+/// The end of the program is left out.
+fn successors(instructions: &[Localized<Instruction>], address: usize) -> Vec<usize> {
+    let next = match instructions[address].value {
+        Instruction::HLT(..) => vec![],
+        Instruction::JMP(target) => vec![target],
+        Instruction::JEQ(_, _, target)
+        | Instruction::JGT(_, _, target)
+        | Instruction::JLT(_, _, target) => vec![address + 1, target],
+        _ => vec![address + 1],
+    };
+
+    next.into_iter()
+        .filter(|next| *next < instructions.len())
+        .collect()
+}
+
+/// Solves a forward data flow problem: `transfer` gives the state after an instruction from the
+/// state before it, `join` merges the states of two paths and returns whether the first one
+/// changed. Returns the state before each instruction, `None` where it cannot be reached.
+fn flow<S, T, J>(
+    instructions: &[Localized<Instruction>],
+    start: S,
+    transfer: T,
+    join: J,
+) -> Vec<Option<S>>
+where
+    S: Clone,
+    T: Fn(&Instruction, &S) -> S,
+    J: Fn(&mut S, &S) -> bool,
+{
+    let mut states: Vec<Option<S>> = vec![None; instructions.len()];
+    if instructions.is_empty() {
+        return states;
+    }
+
+    states[0] = Some(start);
+    let mut pending = vec![0];
+    while let Some(address) = pending.pop() {
+        let Some(state) = &states[address] else {
+            unreachable!()
+        };
+
+        let after = transfer(&instructions[address].value, state);
+        for next in successors(instructions, address) {
+            let changed = match &mut states[next] {
+                Some(state) => join(state, &after),
+                state @ None => {
+                    *state = Some(after.clone());
+                    true
+                }
+            };
+
+            if changed {
+                pending.push(next);
+            }
+        }
+    }
+
+    states
+}
+
+/// Value of the current position of a ring, where it is the same on every path
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Value {
+    Known(u8),
+    /// The whole ring is zero, e.g. right after `mkr`, so rotating it keeps the value
+    Zero,
+}
+
+/// What is known about the rings before an instruction
+#[derive(Clone, Debug, PartialEq)]
+struct Rings {
+    /// Number of rings, if it is the same on every path
+    count: Option<usize>,
+    values: HashMap<RingId, Value>,
+}
+
+impl Rings {
+    fn current(&self, ring: RingId) -> Option<u8> {
+        match self.values.get(&ring)? {
+            Value::Known(value) => Some(*value),
+            Value::Zero => Some(0),
+        }
+    }
+
+    fn set(&mut self, ring: RingId, value: Option<u8>) {
+        match value {
+            Some(0) if self.values.get(&ring) == Some(&Value::Zero) => (),
+            Some(value) => {
+                self.values.insert(ring, Value::Known(value));
+            }
+            None => {
+                self.values.remove(&ring);
+            }
+        }
+    }
+
+    fn after(&self, instruction: &Instruction) -> Self {
+        let mut rings = self.clone();
+        match *instruction {
+            Instruction::MKR(..) => {
+                if let Some(count) = self.count {
+                    if let Ok(ring) = RingId::try_from(count) {
+                        rings.values.insert(ring, Value::Zero);
+                    }
+                }
+                rings.count = self.count.map(|count| count + 1);
+            }
+            Instruction::PUT(ring, value) => rings.set(ring, Some(value)),
+            Instruction::ROT(ring, _) => {
+                if rings.values.get(&ring) != Some(&Value::Zero) {
+                    rings.values.remove(&ring);
+                }
+            }
+            Instruction::SWP(a, b) => {
+                rings.set(a, self.current(b));
+                rings.set(b, self.current(a));
+            }
+            Instruction::INP(ring) => rings.set(ring, None),
+            Instruction::INF(ring, flag) => {
+                rings.set(ring, None);
+                rings.set(flag, None);
+            }
+            Instruction::ADD(a, b, c) => {
+                let value = self.current(a).zip(self.current(b));
+                rings.set(c, value.map(|(a, b)| a.wrapping_add(b)));
+            }
+            Instruction::SUB(a, b, c) if a == b => rings.set(c, Some(0)),
+            Instruction::SUB(a, b, c) => {
+                let value = self.current(a).zip(self.current(b));
+                rings.set(c, value.map(|(a, b)| a.wrapping_sub(b)));
+            }
+            Instruction::MUL(a, b, c) => {
+                let value = match (self.current(a), self.current(b)) {
+                    (Some(0), _) | (_, Some(0)) => Some(0),
+                    (Some(a), Some(b)) => Some(a.wrapping_mul(b)),
+                    _ => None,
+                };
+                rings.set(c, value);
+            }
+            Instruction::DIV(a, b, c) => {
+                // The result of a division by zero depends on the division by zero policy
+                let value = match (self.current(a), self.current(b)) {
+                    (Some(a), Some(b)) if b != 0 => Some(a / b),
+                    _ => None,
+                };
+                rings.set(c, value);
+            }
+            Instruction::OUT(..)
+            | Instruction::ERR(..)
+            | Instruction::JMP(..)
+            | Instruction::JEQ(..)
+            | Instruction::JGT(..)
+            | Instruction::JLT(..)
+            | Instruction::HLT(..) => (),
+        }
+
+        rings
+    }
+
+    /// Keeps only what is known on both paths
+    fn join(&mut self, other: &Self) -> bool {
+        let before = self.clone();
+        if self.count != other.count {
+            self.count = None;
+        }
+        let values = std::mem::take(&mut self.values);
+        self.values = values
+            .into_iter()
+            .filter_map(|(ring, value)| {
+                let joined = match (value, other.values.get(&ring)?) {
+                    (value, other) if value == *other => value,
+                    // 0 on both paths, but only one of them knows the rest of the ring
+                    (Value::Zero, Value::Known(0)) | (Value::Known(0), Value::Zero) => {
+                        Value::Known(0)
+                    }
+                    _ => return None,
+                };
+                Some((ring, joined))
+            })
+            .collect();
+
+        *self != before
+    }
+}
+
+/// Checks a program for likely mistakes, returning the warnings in the order of the instructions
+pub fn check(
+    instructions: &[Localized<Instruction>],
+    labels: &[Localized<DefinedLabel>],
+) -> Vec<Localized<Warning>> {
+    let mut warnings = Vec::new();
+
+    for label in labels {
+        if !label.used {
+            warnings.push(label.transform(Warning::UnusedLabel(label.name.clone())));
+        }
+
+        if label.address == instructions.len() {
+            warnings.push(label.transform(Warning::LabelAtEnd(label.name.clone())));
+        }
+    }
+
+    // The most rings that can exist before each instruction. Rings created in a loop can exist in
+    // any number, up to the number of ring ids.
+    let most_rings = flow(
+        instructions,
+        0,
+        |instruction, rings| match instruction {
+            Instruction::MKR(..) => (rings + 1).min(RingId::MAX as usize + 1),
+            _ => *rings,
+        },
+        |rings, other| {
+            let changed = *other > *rings;
+            *rings = (*rings).max(*other);
+            changed
+        },
+    );
+
+    let start = Rings {
+        count: Some(0),
+        values: HashMap::new(),
+    };
+    let known = flow(
+        instructions,
+        start,
+        |instruction, rings| rings.after(instruction),
+        Rings::join,
+    );
+
+    for (address, instruction) in instructions.iter().enumerate() {
+        let (Some(rings), Some(known)) = (most_rings[address], &known[address]) else {
+            if address == 0 || most_rings[address - 1].is_some() {
+                warnings.push(instruction.transform(Warning::Unreachable));
+            }
+            continue;
+        };
+
+        for ring in instruction.rings() {
+            if ring as usize >= rings {
+                warnings.push(instruction.transform(Warning::UncreatedRing { ring, rings }));
+            }
+        }
+
+        if let Instruction::DIV(_, divisor, _) = instruction.value {
+            if known.current(divisor) == Some(0) {
+                warnings.push(instruction.transform(Warning::DivisionByZero(divisor)));
+            }
+        }
+    }
+
+    warnings.sort_by_key(|warning| (warning.source, warning.line_number, warning.char_number));
+    warnings
+}
+
+/// The text of the comment on `line`, if there is one
+fn comment(line: &str) -> Option<&str> {
+    let mut quote = None;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            _ if escaped => escaped = false,
+            (Some(_), '\\') => escaped = true,
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => (),
+            (None, '\'' | '"') => quote = Some(c),
+            (None, '#') => return Some(&line[i + 1..]),
+            (None, _) => (),
+        }
+    }
+
+    None
+}
+
+/// Whether the comment on `line` allows `lint`
+fn allows(line: &str, lint: Lint) -> bool {
+    let Some(comment) = comment(line) else {
+        return false;
+    };
+
+    let lints = comment
+        .trim()
+        .strip_prefix("allow(")
+        .and_then(|rest| rest.strip_suffix(')'));
+
+    lints.is_some_and(|lints| lints.split(',').any(|name| name.trim() == lint.name()))
+}
+
+/// Whether `warning` is allowed by an `# allow(...)` comment on its line, or on its own line
+/// right before it
+pub fn allowed(sources: &Sources, warning: &Localized<Warning>) -> bool {
+    let Some(file) = sources.get(warning.source) else {
+        return false;
+    };
+
+    let lint = warning.lint();
+    let mut lines = file
+        .text
+        .lines()
+        .skip(warning.line_number.saturating_sub(2));
+    let before = match warning.line_number {
+        1 => None,
+        _ => lines.next(),
+    };
+
+    lines.next().is_some_and(|line| allows(line, lint))
+        || before.is_some_and(|line| line.trim_start().starts_with('#') && allows(line, lint))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{flow, Lint};
+    use crate::{
+        build::{Program, ProgramAssembler},
+        Sources,
+    };
+
+    /// Kind and line of each warning of `source`
+    fn warnings(source: &str) -> Vec<(Lint, usize)> {
+        let warnings = ProgramAssembler::check_file(
+            Path::new("test.rn"),
+            source.as_bytes(),
+            &[],
+            &mut Sources::new(),
+        )
+        .unwrap();
+
+        warnings.iter().map(|w| (w.lint(), w.line_number)).collect()
+    }
+
+    #[test]
+    fn flow_reaches_instructions_along_jumps() {
+        let source = "\
+:top
+    mkr 1
+    jeq 0 0 :end
+    jmp :top
+    out 0
+:end
+    hlt 0";
+        let Program::Localized(instructions, ..) =
+            ProgramAssembler::assemble(source.as_bytes(), true).unwrap()
+        else {
+            unreachable!()
+        };
+
+        // Fewest instructions executed before each instruction
+        let steps = flow(
+            &instructions,
+            0,
+            |_, steps| steps + 1,
+            |steps, other| {
+                let changed = *other < *steps;
+                *steps = (*steps).min(*other);
+                changed
+            },
+        );
+        assert_eq!(steps, [Some(0), Some(1), Some(2), None, Some(2)]);
+    }
+
+    #[test]
+    fn warns_about_unused_labels_and_labels_at_end() {
+        let source = "\
+:main
+    mkr 1
+:.loop
+    jeq 0 0 :.loop
+:unused
+    hlt 0
+:end";
+        assert_eq!(
+            warnings(source),
+            [
+                (Lint::UnusedLabel, 5),
+                (Lint::UnusedLabel, 7),
+                (Lint::LabelAtEnd, 7)
+            ]
+        );
+    }
+
+    #[test]
+    fn warns_about_unreachable_code_once() {
+        let source = "\
+    mkr 1
+    hlt 0
+    out 0
+    out 0";
+        assert_eq!(warnings(source), [(Lint::Unreachable, 3)]);
+    }
+
+    #[test]
+    fn warns_about_uncreated_rings() {
+        let source = "\
+    out 0
+    mkr 1
+    out 0
+    add 0 1 0
+:loop
+    mkr 1
+    out 5
+    jmp :loop";
+        assert_eq!(
+            warnings(source),
+            [(Lint::UncreatedRing, 1), (Lint::UncreatedRing, 4)]
+        );
+    }
+
+    #[test]
+    fn tracks_known_values_for_division_by_zero() {
+        let source = "\
+    mkr 4 mkr 4 mkr 4 mkr 4
+    div 0 1 3
+    put 1 3 div 0 1 3
+    rot 1 1 div 0 1 3
+    rot 2 1 div 0 2 0
+    sub 0 0 1 div 3 1 3
+    put 0 5 put 1 2 sub 0 1 1 div 3 1 3
+    put 2 0 swp 1 2 div 0 1 0
+    inp 1 div 0 1 0";
+        assert_eq!(
+            warnings(source),
+            [
+                (Lint::DivisionByZero, 2),
+                (Lint::DivisionByZero, 5),
+                (Lint::DivisionByZero, 6),
+                (Lint::DivisionByZero, 8)
+            ]
+        );
+    }
+
+    #[test]
+    fn forgets_values_that_differ_between_paths() {
+        let source = "\
+    mkr 1 mkr 1 inp 0
+    jeq 0 0 :zero
+    put 1 1
+:zero
+    div 0 1 0
+    put 1 0
+    jgt 0 0 :end
+    put 1 0
+:end
+    div 0 1 0";
+        assert_eq!(warnings(source), [(Lint::DivisionByZero, 10)]);
+    }
+
+    #[test]
+    fn allows_lints_on_the_same_line() {
+        let source = "\
+:unused # allow(unused-label)
+    mkr 1
+    div 0 0 0  # allow(unreachable, division-by-zero)
+    hlt 0";
+        assert_eq!(warnings(source), []);
+    }
+
+    #[test]
+    fn allows_lints_on_the_line_before() {
+        let source = "\
+# allow(unused-label)
+:unused
+    mkr 1
+    # allow(division-by-zero)
+    div 0 0 0
+    hlt 0
+    out 0 # allow(division-by-zero)";
+        assert_eq!(warnings(source), [(Lint::Unreachable, 7)]);
+    }
+
+    #[test]
+    fn ignores_allow_outside_comments() {
+        let source = "\
+    mkr 1 mkr 1
+    .print 0 \"# allow(division-by-zero)\" div 0 1 0
+    put 0 0 # allow(division-by-zero)
+    div 0 0 0";
+        assert_eq!(
+            warnings(source),
+            [(Lint::DivisionByZero, 2), (Lint::DivisionByZero, 4)]
+        );
+    }
+}
@@ -0,0 +1,324 @@
+use std::{borrow::Cow, io::Write};
+
+use crate::{
+    error::{Diagnostic, Diagnostics},
+    lint::Warning,
+    Localized, MaybeLocalized, SourceId, Sources,
+};
+
+const RESET: &str = "\x1b[0m";
+const BOLD: &str = "\x1b[1m";
+const RED: &str = "\x1b[1;31m";
+const BLUE: &str = "\x1b[1;34m";
+const CYAN: &str = "\x1b[1;36m";
+const YELLOW: &str = "\x1b[1;33m";
+
+const TAB_WIDTH: usize = 4;
+
+/// Renders [`Diagnostics`] in the style of rustc: the error message, the file location,
+/// the offending source line with the span of the error underlined, and an optional hint.
+///
+/// ```text
+/// error: Label not found: loop_ed
+///  --> program.rn:9:1
+///   |
+/// 9 | jeq 0 0 :loop_ed
+///   | ^^^^^^^^^^^^^^^^
+///   = hint: did you mean :loop_end?
+/// ```
+pub struct DiagnosticRenderer<'a> {
+    /// Name and lines of each source file, indexed by [`SourceId`]
+    files: Vec<(Cow<'a, str>, Vec<&'a str>)>,
+    color: bool,
+}
+
+impl<'a> DiagnosticRenderer<'a> {
+    /// `source` is the text the diagnostics refer to, `color` enables ANSI escape codes
+    pub fn new(file_name: &'a str, source: &'a str, color: bool) -> Self {
+        Self {
+            files: vec![(Cow::Borrowed(file_name), source.lines().collect())],
+            color,
+        }
+    }
+
+    /// Renders diagnostics located in any of the files of a program, e.g. one with includes
+    pub fn with_sources(sources: &'a Sources, color: bool) -> Self {
+        Self {
+            files: sources
+                .iter()
+                .map(|(_, file)| (file.path.to_string_lossy(), file.text.lines().collect()))
+                .collect(),
+            color,
+        }
+    }
+
+    fn file_name(&self, source: SourceId) -> Cow<'_, str> {
+        match self.files.get(source.0) {
+            Some((name, _)) => Cow::Borrowed(name),
+            None => Cow::Owned(format!("<source {}>", source.0)),
+        }
+    }
+
+    fn paint(&self, style: &'static str) -> &'static str {
+        if self.color {
+            style
+        } else {
+            ""
+        }
+    }
+
+    fn reset(&self) -> &'static str {
+        self.paint(RESET)
+    }
+
+    /// Expands tabs, so that the underline lines up with the displayed source line
+    fn expand_tabs(line: &str) -> String {
+        line.replace('\t', &" ".repeat(TAB_WIDTH))
+    }
+
+    /// Display column of the 1-based `char_number` on `line`, with tabs expanded
+    fn display_column(line: &str, char_number: usize) -> usize {
+        let chars = line.chars().take(char_number.saturating_sub(1));
+        chars.map(|c| if c == '\t' { TAB_WIDTH } else { 1 }).sum()
+    }
+
+    /// Writes the source line of `location` with its span marked by `marker`,
+    /// followed by `label` if there is one
+    fn snippet<W>(
+        &self,
+        out: &mut W,
+        gutter: &str,
+        location: &Localized<()>,
+        (marker, style): (char, &'static str),
+        label: Option<&str>,
+    ) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        let (blue, reset) = (self.paint(BLUE), self.reset());
+        let line = location.line_number;
+        let lines = self.files.get(location.source.0).map(|(_, lines)| lines);
+        let Some(text) = lines.and_then(|lines| lines.get(line.wrapping_sub(1))) else {
+            return Ok(());
+        };
+
+        let start = Self::display_column(text, location.char_number);
+        // Spans reaching past the line are underlined up to its end
+        let end = if location.end.line_number == line {
+            Self::display_column(text, location.end.char_number)
+        } else {
+            Self::display_column(text, usize::MAX)
+        };
+
+        let number = line.to_string();
+        writeln!(out, "{} {}|{}", gutter, blue, reset)?;
+        writeln!(
+            out,
+            "{}{}{} |{} {}",
+            blue,
+            " ".repeat(gutter.len() - number.len()),
+            number,
+            reset,
+            Self::expand_tabs(text)
+        )?;
+        write!(
+            out,
+            "{} {}|{} {}{}{}",
+            gutter,
+            blue,
+            reset,
+            " ".repeat(start),
+            style,
+            marker.to_string().repeat(end.saturating_sub(start).max(1)),
+        )?;
+        match label {
+            Some(label) => writeln!(out, " {}{}", label, reset),
+            None => writeln!(out, "{}", reset),
+        }
+    }
+
+    /// Writes the file, line and character of `location`, after `arrow`
+    fn origin<W>(
+        &self,
+        out: &mut W,
+        gutter: &str,
+        arrow: &str,
+        location: &Localized<()>,
+    ) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(
+            out,
+            "{}{}{}{} {}:{}:{}",
+            gutter,
+            self.paint(BLUE),
+            arrow,
+            self.reset(),
+            self.file_name(location.source),
+            location.line_number,
+            location.char_number
+        )
+    }
+
+    pub fn render_one<W>(&self, diagnostic: &Diagnostic, out: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        let (red, blue, cyan, bold, reset) = (
+            self.paint(RED),
+            self.paint(BLUE),
+            self.paint(CYAN),
+            self.paint(BOLD),
+            self.reset(),
+        );
+
+        writeln!(
+            out,
+            "{}error{}{}: {}{}",
+            red,
+            reset,
+            bold,
+            diagnostic.error.as_ref(),
+            reset
+        )?;
+
+        let MaybeLocalized::Localized(location) = &diagnostic.error else {
+            if let Some(hint) = &diagnostic.hint {
+                writeln!(out, "{}hint{}: {}", cyan, reset, hint)?;
+            }
+
+            return Ok(());
+        };
+
+        let line = location.line_number;
+        let widest = diagnostic
+            .notes
+            .iter()
+            .map(|note| note.line_number)
+            .fold(line, usize::max);
+        let gutter = " ".repeat(widest.to_string().len());
+
+        self.origin(out, &gutter, "-->", &location.transform(()))?;
+        self.snippet(out, &gutter, &location.transform(()), ('^', red), None)?;
+
+        for note in &diagnostic.notes {
+            self.origin(out, &gutter, ":::", &note.transform(()))?;
+            self.snippet(
+                out,
+                &gutter,
+                &note.transform(()),
+                ('-', blue),
+                Some(note.as_str()),
+            )?;
+        }
+
+        if let Some(hint) = &diagnostic.hint {
+            writeln!(
+                out,
+                "{} {}={} {}hint{}: {}",
+                gutter, blue, reset, cyan, reset, hint
+            )?;
+        }
+
+        Ok(())
+    }
+
+    pub fn render<W>(&self, diagnostics: &Diagnostics, out: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        for diagnostic in diagnostics {
+            self.render_one(diagnostic, out)?;
+            writeln!(out)?;
+        }
+
+        if diagnostics.len() > 1 {
+            writeln!(
+                out,
+                "{}error{}{}: aborting due to {} previous errors{}",
+                self.paint(RED),
+                self.reset(),
+                self.paint(BOLD),
+                diagnostics.len(),
+                self.reset()
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Renders a warning of `rings check`, with the comment that allows it
+    pub fn render_warning<W>(
+        &self,
+        warning: &Localized<Warning>,
+        out: &mut W,
+    ) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        let (yellow, blue, cyan, bold, reset) = (
+            self.paint(YELLOW),
+            self.paint(BLUE),
+            self.paint(CYAN),
+            self.paint(BOLD),
+            self.reset(),
+        );
+
+        writeln!(
+            out,
+            "{}warning{}{}: {}{}",
+            yellow, reset, bold, warning.value, reset
+        )?;
+
+        let gutter = " ".repeat(warning.line_number.to_string().len());
+        self.origin(out, &gutter, "-->", &warning.transform(()))?;
+        self.snippet(out, &gutter, &warning.transform(()), ('^', yellow), None)?;
+
+        if let Some(hint) = warning.hint() {
+            writeln!(
+                out,
+                "{} {}={} {}hint{}: {}",
+                gutter, blue, reset, cyan, reset, hint
+            )?;
+        }
+        writeln!(
+            out,
+            "{} {}={} {}note{}: allow with # allow({})",
+            gutter,
+            blue,
+            reset,
+            bold,
+            reset,
+            warning.lint().name()
+        )
+    }
+
+    pub fn render_warnings<W>(
+        &self,
+        warnings: &[Localized<Warning>],
+        out: &mut W,
+    ) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        for warning in warnings {
+            self.render_warning(warning, out)?;
+            writeln!(out)?;
+        }
+
+        if warnings.len() > 1 {
+            writeln!(
+                out,
+                "{}warning{}{}: {} warnings emitted{}",
+                self.paint(YELLOW),
+                self.reset(),
+                self.paint(BOLD),
+                warnings.len(),
+                self.reset()
+            )?;
+        }
+
+        Ok(())
+    }
+}
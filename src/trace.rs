@@ -0,0 +1,315 @@
+use std::io::Write;
+
+use crate::{
+    error::{MaybeLocalizedRingsResult, RingsError},
+    instruction::Instruction,
+    io::RingsIo,
+    vm::{ExitCode, RingId, RingsVM, StepOutcome},
+    MaybeLocalized, SourceId,
+};
+
+#[derive(Debug)]
+pub struct UnknownTraceFormat(String);
+
+impl std::error::Error for UnknownTraceFormat {}
+
+impl std::fmt::Display for UnknownTraceFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Unknown trace format: {}. Expected text or json", self.0)
+    }
+}
+
+#[derive(Clone, Copy, Default, Debug)]
+pub enum TraceFormat {
+    /// Human readable, one line per instruction
+    #[default]
+    Text,
+    /// JSON Lines, one object per instruction
+    Json,
+}
+
+impl std::str::FromStr for TraceFormat {
+    type Err = UnknownTraceFormat;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" | "jsonl" => Ok(Self::Json),
+            s => Err(UnknownTraceFormat(s.to_owned())),
+        }
+    }
+}
+
+/// Current value of a ring before and after an instruction. `None` if the ring did not exist.
+#[derive(Clone, Copy, Debug)]
+pub struct RingChange {
+    pub ring: RingId,
+    pub before: Option<u8>,
+    pub after: Option<u8>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TraceRecord {
+    pub pc: usize,
+    /// Source file, line and character of the instruction, if the program is localized
+    pub location: Option<(SourceId, usize, usize)>,
+    pub instruction: Instruction,
+    pub rings: Vec<RingChange>,
+    /// The runtime error raised by the instruction
+    pub error: Option<String>,
+}
+
+impl TraceRecord {
+    fn value(vm: &RingsVM, ring: RingId) -> Option<u8> {
+        vm.rings.get(ring as usize).map(|r| *r.current())
+    }
+
+    fn touched_rings(vm: &RingsVM, instruction: &Instruction) -> Vec<RingId> {
+        match instruction {
+            // The ring about to be created
+            Instruction::MKR(..) => RingId::try_from(vm.rings.len()).into_iter().collect(),
+            instruction => instruction.rings(),
+        }
+    }
+
+    pub fn write_text<W>(&self, out: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        write!(out, "{:04}", self.pc)?;
+        let location = match self.location {
+            Some((source, line, char)) => format!("{}:{}@{}", source.0, line, char),
+            None => String::new(),
+        };
+        write!(out, "  {:<10}", location)?;
+        if self.rings.is_empty() && self.error.is_none() {
+            write!(out, " {}", self.instruction)?;
+        } else {
+            write!(out, " {:<16}", self.instruction.to_string())?;
+        }
+
+        // Rings that do not exist are shown as `--`
+        fn value(v: Option<u8>) -> String {
+            v.map_or_else(|| "--".to_owned(), |v| format!("{:02X}", v))
+        }
+
+        for change in self.rings.iter() {
+            write!(
+                out,
+                " r{}:{} -> {}",
+                change.ring,
+                value(change.before),
+                value(change.after)
+            )?;
+        }
+
+        if let Some(error) = &self.error {
+            write!(out, " error: {}", error)?;
+        }
+
+        writeln!(out)
+    }
+
+    pub fn write_json<W>(&self, out: &mut W) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        fn opt<T: std::fmt::Display>(v: Option<T>) -> String {
+            v.map(|v| v.to_string())
+                .unwrap_or_else(|| "null".to_owned())
+        }
+
+        /// A JSON string literal, escaping quotes, backslashes and control characters
+        fn string(s: &str) -> String {
+            let mut out = String::with_capacity(s.len() + 2);
+            out.push('"');
+            for c in s.chars() {
+                match c {
+                    '"' | '\\' => {
+                        out.push('\\');
+                        out.push(c);
+                    }
+                    c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04X}", c as u32)),
+                    c => out.push(c),
+                }
+            }
+            out.push('"');
+            out
+        }
+
+        write!(
+            out,
+            "{{\"pc\":{},\"source\":{},\"line\":{},\"char\":{},\"instruction\":{},\"rings\":[",
+            self.pc,
+            opt(self.location.map(|l| l.0 .0)),
+            opt(self.location.map(|l| l.1)),
+            opt(self.location.map(|l| l.2)),
+            string(&self.instruction.to_string())
+        )?;
+
+        for (i, change) in self.rings.iter().enumerate() {
+            if i != 0 {
+                write!(out, ",")?;
+            }
+
+            write!(
+                out,
+                "{{\"ring\":{},\"before\":{},\"after\":{}}}",
+                change.ring,
+                opt(change.before),
+                opt(change.after)
+            )?;
+        }
+
+        write!(out, "]")?;
+        if let Some(error) = &self.error {
+            write!(out, ",\"error\":{}", string(error))?;
+        }
+
+        writeln!(out, "}}")
+    }
+
+    pub fn write<W>(&self, out: &mut W, format: TraceFormat) -> std::io::Result<()>
+    where
+        W: Write,
+    {
+        match format {
+            TraceFormat::Text => self.write_text(out),
+            TraceFormat::Json => self.write_json(out),
+        }
+    }
+}
+
+/// Steps a [`RingsVM`] and writes one [`TraceRecord`] per executed instruction
+pub struct Tracer<W> {
+    out: W,
+    format: TraceFormat,
+}
+
+impl<W> Tracer<W>
+where
+    W: Write,
+{
+    pub fn new(out: W, format: TraceFormat) -> Self {
+        Self { out, format }
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    pub fn step<I>(
+        &mut self,
+        vm: &mut RingsVM,
+        io: &mut I,
+    ) -> MaybeLocalizedRingsResult<StepOutcome>
+    where
+        I: RingsIo,
+    {
+        let pc = vm.pc;
+        let current = vm.program().get(pc).filter(|_| !vm.halted());
+        let before = match &current {
+            Some(instr) => TraceRecord::touched_rings(vm, instr)
+                .into_iter()
+                .map(|ring| (ring, TraceRecord::value(vm, ring)))
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let result = vm.step(io);
+        // The instruction that failed is traced as well, with the error
+        let (instruction, error) = match &result {
+            Ok(outcome) => (outcome.instruction.clone(), None),
+            Err(e) => (current, Some(e.as_ref().to_string())),
+        };
+
+        if let Some(instr) = &instruction {
+            let record = TraceRecord {
+                pc,
+                location: match instr {
+                    MaybeLocalized::Localized(l) => Some((l.source, l.line_number, l.char_number)),
+                    MaybeLocalized::General(..) => None,
+                },
+                instruction: **instr,
+                rings: before
+                    .into_iter()
+                    .map(|(ring, before)| RingChange {
+                        ring,
+                        before,
+                        after: TraceRecord::value(vm, ring),
+                    })
+                    .collect(),
+                error,
+            };
+
+            record
+                .write(&mut self.out, self.format)
+                .map_err(RingsError::from)?;
+        }
+
+        if result.is_err() {
+            self.out.flush().map_err(RingsError::from)?;
+        }
+
+        result
+    }
+
+    /// Runs the VM until it halts, tracing every instruction
+    pub fn run<I>(&mut self, vm: &mut RingsVM, io: &mut I) -> MaybeLocalizedRingsResult<ExitCode>
+    where
+        I: RingsIo,
+    {
+        let exit_code = loop {
+            let outcome = self.step(vm, io)?;
+            if let Some(exit_code) = outcome.exit_code {
+                break exit_code;
+            }
+        };
+
+        self.out.flush().map_err(RingsError::from)?;
+        Ok(exit_code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RingChange, TraceRecord};
+    use crate::{instruction::Instruction, SourceId};
+
+    fn record(error: Option<&str>) -> TraceRecord {
+        TraceRecord {
+            pc: 2,
+            location: Some((SourceId(1), 3, 5)),
+            instruction: Instruction::PUT(1, 3),
+            rings: vec![RingChange {
+                ring: 1,
+                before: None,
+                after: Some(3),
+            }],
+            error: error.map(str::to_owned),
+        }
+    }
+
+    #[test]
+    fn writes_text() {
+        let mut out = Vec::new();
+        record(None).write_text(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "0002  1:3@5      put 1 3          r1:-- -> 03\n"
+        );
+    }
+
+    #[test]
+    fn escapes_json_strings() {
+        let mut out = Vec::new();
+        record(Some("a \"b\"\\\n\t\u{1}"))
+            .write_json(&mut out)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "{\"pc\":2,\"source\":1,\"line\":3,\"char\":5,\"instruction\":\"put 1 3\",\
+             \"rings\":[{\"ring\":1,\"before\":null,\"after\":3}],\
+             \"error\":\"a \\\"b\\\"\\\\\\u000A\\u0009\\u0001\"}\n"
+        );
+    }
+}
@@ -1,12 +1,23 @@
 use std::ops::{Index, IndexMut};
 
-use crate::{build::Program, error::MaybeLocalizedRingsResult, io::RingsIo, MaybeLocalized};
+use crate::{
+    build::Program,
+    error::{Diagnostic, Diagnostics, MaybeLocalizedRingsResult, RingsError},
+    instruction::Instruction,
+    io::RingsIo,
+    MaybeLocalized,
+};
 
 pub(crate) type RuntimeResult<T> = Result<T, RuntimeError>;
 #[derive(Debug)]
 pub enum RuntimeError {
     InvalidRing(RingId),
     ZeroRingSize,
+    StepLimitExceeded(u64),
+    RingLimitExceeded(usize),
+    MemoryLimitExceeded(usize),
+    DivisionByZero { dividend: RingId, divisor: RingId },
+    EndOfInput(RingId),
 }
 
 impl std::error::Error for RuntimeError {}
@@ -16,6 +27,19 @@ impl std::fmt::Display for RuntimeError {
         match self {
             Self::InvalidRing(i) => write!(f, "Invalid ring {}", i),
             Self::ZeroRingSize => write!(f, "Attempting to create a ring with a zero size"),
+            Self::StepLimitExceeded(max) => {
+                write!(f, "Step limit of {} instructions exceeded", max)
+            }
+            Self::RingLimitExceeded(max) => write!(f, "Ring limit of {} rings exceeded", max),
+            Self::MemoryLimitExceeded(max) => {
+                write!(f, "Memory limit of {} bytes of ring memory exceeded", max)
+            }
+            Self::DivisionByZero { dividend, divisor } => write!(
+                f,
+                "Division by zero: ring {} divided by ring {}, which holds 0",
+                dividend, divisor
+            ),
+            Self::EndOfInput(ring) => write!(f, "End of input while reading into ring {}", ring),
         }
     }
 }
@@ -88,50 +112,365 @@ impl std::fmt::Display for Ring {
 }
 
 pub type ExitCode = u8;
+
+/// Exit status of `rings run` when the VM fails with a [`RuntimeError`]
+pub const EXIT_RUNTIME_ERROR: ExitCode = 70;
+
+/// Result of a single [`RingsVM::step`]
+#[derive(Clone, Debug)]
+pub struct StepOutcome {
+    /// Address of the executed instruction
+    pub pc: usize,
+    /// The executed instruction, `None` if the VM was already halted or ran past the end of the program
+    pub instruction: Option<MaybeLocalized<Instruction>>,
+    /// Set once the VM has halted
+    pub exit_code: Option<ExitCode>,
+}
+
+impl StepOutcome {
+    pub fn halted(&self) -> bool {
+        self.exit_code.is_some()
+    }
+}
+
+/// Resource limits of a [`RingsVM`]. `None` means unlimited.
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    /// Maximum number of executed instructions
+    pub max_steps: Option<u64>,
+    /// Maximum number of rings created by MKR
+    pub max_rings: Option<usize>,
+    /// Maximum total length of all rings, in bytes
+    pub max_memory: Option<usize>,
+}
+
+#[derive(Debug)]
+pub struct InvalidDivisionByZeroPolicy(String);
+
+impl std::error::Error for InvalidDivisionByZeroPolicy {}
+
+impl std::fmt::Display for InvalidDivisionByZeroPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid division by zero policy: {}. Expected error or a value 0-255",
+            self.0
+        )
+    }
+}
+
+/// What DIV does when the divisor ring holds 0
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq)]
+pub enum DivisionByZeroPolicy {
+    /// Fail with [`RuntimeError::DivisionByZero`]
+    #[default]
+    Error,
+    /// Store the given value as the result, for compatibility with other interpreters
+    Define(u8),
+}
+
+impl std::str::FromStr for DivisionByZeroPolicy {
+    type Err = InvalidDivisionByZeroPolicy;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = match s {
+            "error" => return Ok(Self::Error),
+            s if s.starts_with("0x") || s.starts_with("0X") => u8::from_str_radix(&s[2..], 16),
+            s => s.parse(),
+        };
+
+        value
+            .map(Self::Define)
+            .map_err(|_| InvalidDivisionByZeroPolicy(s.to_owned()))
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidEofPolicy(String);
+
+impl std::error::Error for InvalidEofPolicy {}
+
+impl std::fmt::Display for InvalidEofPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid end of input policy: {}. Expected error, unchanged or a value 0-255",
+            self.0
+        )
+    }
+}
+
+/// What INP does when the input is exhausted
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EofPolicy {
+    /// Store the given value in the ring. 0xFF by default, which cannot be told apart from input data.
+    Sentinel(u8),
+    /// Leave the ring unchanged
+    Unchanged,
+    /// Fail with [`RuntimeError::EndOfInput`]
+    Error,
+}
+
+impl Default for EofPolicy {
+    fn default() -> Self {
+        Self::Sentinel(0xFF)
+    }
+}
+
+impl std::str::FromStr for EofPolicy {
+    type Err = InvalidEofPolicy;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = match s {
+            "error" => return Ok(Self::Error),
+            "unchanged" => return Ok(Self::Unchanged),
+            s if s.starts_with("0x") || s.starts_with("0X") => u8::from_str_radix(&s[2..], 16),
+            s => s.parse(),
+        };
+
+        value
+            .map(Self::Sentinel)
+            .map_err(|_| InvalidEofPolicy(s.to_owned()))
+    }
+}
+
 #[derive(Default)]
 pub struct RingsVM {
     pub rings: Vec<Ring>,
     pub pc: usize,
     pub exit_code: Option<ExitCode>,
+    pub limits: Limits,
+    pub division_by_zero: DivisionByZeroPolicy,
+    pub end_of_input: EofPolicy,
+    steps: u64,
+    memory: usize,
+    program: Program,
 }
 
 impl RingsVM {
+    pub fn new(program: Program) -> Self {
+        let mut vm = Self::default();
+        vm.load(program);
+        vm
+    }
+
+    /// Replaces the loaded program and resets the VM state
+    pub fn load(&mut self, program: Program) {
+        self.program = program;
+        self.reset();
+    }
+
+    /// Resets the VM state, keeping the loaded program
+    pub fn reset(&mut self) {
+        self.rings.clear();
+        self.pc = 0;
+        self.exit_code = None;
+        self.steps = 0;
+        self.memory = 0;
+    }
+
+    pub fn program(&self) -> &Program {
+        &self.program
+    }
+
+    /// The diagnostics of an error returned by [`Self::step`] or [`Self::run`]. Runtime errors get
+    /// notes pointing at the macro calls the faulting instruction was expanded from.
+    pub fn diagnostics(&self, error: MaybeLocalized<RingsError>) -> Diagnostics {
+        let call_sites = match &error {
+            MaybeLocalized::Localized(..) => self.program.call_sites(self.pc),
+            MaybeLocalized::General(..) => &[],
+        };
+
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.push_diagnostic(Diagnostic::new(error, None).with_call_sites(call_sites));
+        diagnostics
+    }
+
+    pub fn halted(&self) -> bool {
+        self.exit_code.is_some()
+    }
+
+    /// Number of instructions executed since the last reset
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Total length of all rings, in bytes
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    pub(crate) fn make_ring(&mut self, capacity: u8) -> RuntimeResult<()> {
+        if let Some(max) = self.limits.max_rings {
+            if self.rings.len() >= max {
+                return Err(RuntimeError::RingLimitExceeded(max));
+            }
+        }
+
+        let memory = self.memory + capacity as usize;
+        if let Some(max) = self.limits.max_memory {
+            if memory > max {
+                return Err(RuntimeError::MemoryLimitExceeded(max));
+            }
+        }
+
+        self.rings.push(Ring::new(capacity)?);
+        self.memory = memory;
+        Ok(())
+    }
+
     pub(crate) fn get_ring(&mut self, index: RingId) -> RuntimeResult<&mut Ring> {
         self.rings
             .get_mut(index as usize)
             .ok_or(RuntimeError::InvalidRing(index))
     }
 
-    pub fn execute<I>(program: &Program, io: &mut I) -> MaybeLocalizedRingsResult<ExitCode>
+    /// Executes a single instruction. Running past the end of the program halts the VM with exit code 0.
+    /// A runtime error leaves the program counter at the faulting instruction.
+    pub fn step<I>(&mut self, io: &mut I) -> MaybeLocalizedRingsResult<StepOutcome>
     where
         I: RingsIo,
     {
-        let mut vm = Self::default();
+        let pc = self.pc;
 
-        let exit_code = loop {
-            let Some(instr) = program.get(vm.pc) else {
-                break 0;
-            };
+        if self.halted() {
+            return Ok(StepOutcome {
+                pc,
+                instruction: None,
+                exit_code: self.exit_code,
+            });
+        }
 
-            vm.pc += 1;
-            //println!("{:?}", instr);
+        let Some(instr) = self.program.get(pc) else {
+            self.exit_code = Some(0);
+            io.flush();
+            return Ok(StepOutcome {
+                pc,
+                instruction: None,
+                exit_code: self.exit_code,
+            });
+        };
 
-            if let Err(e) = instr.execute(&mut vm, io) {
-                return instr.transform(Err(e.into()));
+        if let Some(max) = self.limits.max_steps {
+            if self.steps >= max {
+                io.flush();
+                return Err(instr.transform(RuntimeError::StepLimitExceeded(max).into()));
             }
+        }
+
+        self.pc += 1;
+        self.steps += 1;
+
+        if let Err(e) = instr.execute(self, io) {
+            self.pc = pc;
+            io.flush();
+            return Err(instr.transform(e.into()));
+        }
+
+        if self.halted() {
+            io.flush();
+        }
+
+        Ok(StepOutcome {
+            pc,
+            instruction: Some(instr),
+            exit_code: self.exit_code,
+        })
+    }
 
-            /*if let Ok(ring) = vm.get_ring(0) {
-                println!("{}", ring);
+    /// Steps until the VM halts or `predicate` returns true after a step. Returns the last outcome.
+    pub fn run_until<I, P>(
+        &mut self,
+        io: &mut I,
+        mut predicate: P,
+    ) -> MaybeLocalizedRingsResult<StepOutcome>
+    where
+        I: RingsIo,
+        P: FnMut(&Self) -> bool,
+    {
+        loop {
+            let outcome = self.step(io)?;
+            if outcome.halted() || (predicate)(self) {
+                return Ok(outcome);
             }
-            if let Ok(ring) = vm.get_ring(1) {
-                println!("{}", ring);
-            }*/
+        }
+    }
+
+    /// Executes at most `n_steps` instructions, stopping early if the VM halts.
+    /// Returns the last outcome, `None` if no step was taken.
+    pub fn run_for<I>(
+        &mut self,
+        io: &mut I,
+        n_steps: usize,
+    ) -> MaybeLocalizedRingsResult<Option<StepOutcome>>
+    where
+        I: RingsIo,
+    {
+        let mut last = None;
+        for _ in 0..n_steps {
+            let outcome = self.step(io)?;
+            let halted = outcome.halted();
+            last = Some(outcome);
 
-            if let Some(exit_code) = vm.exit_code {
-                break exit_code;
+            if halted {
+                break;
             }
-        };
+        }
+
+        Ok(last)
+    }
+
+    /// Runs the loaded program until it halts
+    pub fn run<I>(&mut self, io: &mut I) -> MaybeLocalizedRingsResult<ExitCode>
+    where
+        I: RingsIo,
+    {
+        let outcome = self.run_until(io, |_| false)?;
+        Ok(outcome.exit_code.unwrap_or_default())
+    }
+
+    pub fn execute<I>(program: &Program, io: &mut I) -> MaybeLocalizedRingsResult<ExitCode>
+    where
+        I: RingsIo,
+    {
+        Self::new(program.clone()).run(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RingsVM;
+    use crate::{build::ProgramAssembler, io::BufferIo};
+
+    #[test]
+    fn runtime_errors_note_macro_calls() {
+        let source = "\
+.macro out1
+    out 1
+.endm
+.macro twice
+    out1
+    out1
+.endm
+    mkr 1
+    twice";
+        let program = ProgramAssembler::assemble(source.as_bytes(), true).unwrap();
+        let mut vm = RingsVM::new(program);
+        let error = vm.run(&mut BufferIo::new("")).unwrap_err();
+        assert_eq!(vm.pc, 1);
 
-        MaybeLocalized::General(Ok(exit_code))
+        let diagnostics = vm.diagnostics(error);
+        let diagnostic = diagnostics.iter().next().unwrap();
+        assert_eq!(diagnostic.location().map(|l| l.1), Some(2));
+        let notes: Vec<_> = diagnostic
+            .notes
+            .iter()
+            .map(|note| (note.line_number, note.value.as_str()))
+            .collect();
+        assert_eq!(
+            notes,
+            [
+                (5, "in this expansion of macro out1"),
+                (9, "in this expansion of macro twice")
+            ]
+        );
     }
 }